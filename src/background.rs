@@ -0,0 +1,146 @@
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::sprite::{MaterialMesh2dBundle, Mesh2dHandle};
+
+use crate::coords::LevelCoords;
+use crate::ldtk::{BackgroundFit, LevelBackground};
+
+// Behind every layer, the 2d camera sees down to z = -0.1
+pub const BACKGROUND_Z: f32 = -0.05;
+
+// Part of the background image drawn somewhere in the level
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackgroundQuad {
+    // LDtk pixels, (0, 0) is the top left of the level
+    pub top_left: Vec2,
+    pub size: Vec2,
+    // Image pixels
+    pub crop_min: Vec2,
+    pub crop_size: Vec2,
+}
+
+// Places the image like LDtk does: scaled for the fit mode, cropped where it's
+// larger than the level and anchored by the pivot. Repeated images are tiled
+// over the whole level starting from where the pivot puts the first one.
+pub fn background_quads(fit: BackgroundFit, pivot: Vec2, image_size: Vec2, level_size: Vec2) -> Vec<BackgroundQuad> {
+    if image_size.min_element() <= 0.0 || level_size.min_element() <= 0.0 {
+        return Vec::new();
+    }
+
+    if fit == BackgroundFit::Repeat {
+        return repeat_quads(pivot, image_size, level_size);
+    }
+
+    let scale = match fit {
+        BackgroundFit::Unscaled | BackgroundFit::Repeat => Vec2::ONE,
+        BackgroundFit::Contain => Vec2::splat((level_size / image_size).min_element()),
+        BackgroundFit::Cover => Vec2::splat((level_size / image_size).max_element()),
+        BackgroundFit::CoverDirty => level_size / image_size,
+    };
+
+    let scaled_size = image_size * scale;
+    let crop_size = Vec2::new(
+        if scaled_size.x > level_size.x { level_size.x / scale.x } else { image_size.x },
+        if scaled_size.y > level_size.y { level_size.y / scale.y } else { image_size.y },
+    );
+    let size = crop_size * scale;
+    vec![BackgroundQuad {
+        top_left: (level_size - size) * pivot,
+        size,
+        crop_min: (image_size - crop_size) * pivot,
+        crop_size,
+    }]
+}
+
+fn repeat_quads(pivot: Vec2, image_size: Vec2, level_size: Vec2) -> Vec<BackgroundQuad> {
+    // step back from the pivoted image until the whole level is covered
+    let anchor = (level_size - image_size) * pivot;
+    let first = anchor - (anchor / image_size).ceil() * image_size;
+
+    let mut quads = Vec::new();
+    let mut y = first.y;
+    while y < level_size.y {
+        let mut x = first.x;
+        while x < level_size.x {
+            // clipped to the level
+            let min = Vec2::new(x, y).max(Vec2::ZERO);
+            let max = (Vec2::new(x, y) + image_size).min(level_size);
+            if max.x > min.x && max.y > min.y {
+                quads.push(BackgroundQuad {
+                    top_left: min,
+                    size: max - min,
+                    crop_min: min - Vec2::new(x, y),
+                    crop_size: max - min,
+                });
+            }
+            x += image_size.x;
+        }
+        y += image_size.y;
+    }
+    quads
+}
+
+// Spawns the background image of a level as one mesh. None when the image
+// isn't loaded or nothing of it ends up in the level.
+pub fn spawn_background(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    images: &Assets<Image>,
+    background: &LevelBackground,
+    level_size: Vec2,
+    coords: &LevelCoords,
+) -> Option<Entity> {
+    let image_size = match images.get(&background.image) {
+        Some(image) => image.size(),
+        None => {
+            println!("Background image is not loaded, skipping it");
+            return None;
+        }
+    };
+
+    let quads = background_quads(background.fit, background.pivot, image_size, level_size);
+    if quads.is_empty() {
+        return None;
+    }
+
+    let mut positions = Vec::with_capacity(quads.len() * 4);
+    let mut normals = Vec::with_capacity(quads.len() * 4);
+    let mut uvs = Vec::with_capacity(quads.len() * 4);
+    let mut indices = Vec::with_capacity(quads.len() * 6);
+    for quad in quads.iter() {
+        let top_left = coords.ldtk_px_to_world(quad.top_left);
+        let bottom_right = coords.ldtk_px_to_world(quad.top_left + quad.size);
+        let uv_min = quad.crop_min / image_size;
+        let uv_max = (quad.crop_min + quad.crop_size) / image_size;
+
+        let first = positions.len() as u32;
+        positions.extend([
+            [top_left.x, top_left.y, 0.0],
+            [bottom_right.x, top_left.y, 0.0],
+            [bottom_right.x, bottom_right.y, 0.0],
+            [top_left.x, bottom_right.y, 0.0],
+        ]);
+        normals.extend([[0.0, 0.0, 1.0]; 4]);
+        uvs.extend([[uv_min.x, uv_min.y], [uv_max.x, uv_min.y], [uv_max.x, uv_max.y], [uv_min.x, uv_max.y]]);
+        indices.extend([first, first + 2, first + 1, first, first + 3, first + 2]);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+
+    Some(commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            mesh: Mesh2dHandle(meshes.add(mesh)),
+            material: materials.add(ColorMaterial {
+                color: Color::WHITE,
+                texture: Some(background.image.clone()),
+            }),
+            transform: Transform::from_xyz(0.0, 0.0, BACKGROUND_Z),
+            ..Default::default()
+        })
+        .id())
+}
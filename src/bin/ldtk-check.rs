@@ -0,0 +1,180 @@
+// Checks an LDtk project for anything the game can't load, without opening a window.
+//
+//     cargo run --bin ldtk-check -- assets/map.ldtk [--player Player] [--start-level Level_0] [--deny-warnings]
+//
+// A start level without the player entity is an error, other levels without it
+// are warnings since in a world the player usually starts in one level only.
+// The start level is the first level of the project unless another one is given.
+// Tiles without a collider become squares, each layer using them gets a warning
+// listing their ids.
+// Exits with 1 when there are errors (or warnings with --deny-warnings), 2 when
+// the project can't be read at all.
+
+use bevy::asset::HandleId;
+use bevy::prelude::*;
+use ldtk_rust::Level;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::process;
+
+use my_bevy_game::ldtk::{relative_to, LdtkLevel, LdtkProject};
+use my_bevy_game::level;
+
+#[derive(Default)]
+struct Report {
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+impl Report {
+    fn error(&mut self, message: String) {
+        println!("error: {}", message);
+        self.errors.push(message);
+    }
+
+    fn warning(&mut self, message: String) {
+        println!("warning: {}", message);
+        self.warnings.push(message);
+    }
+}
+
+fn main() {
+    let mut project_path = None;
+    let mut player_id = "Player".to_string();
+    let mut start_level = None;
+    let mut deny_warnings = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--player" => match args.next() {
+                Some(id) => player_id = id,
+                None => usage(),
+            },
+            "--start-level" => match args.next() {
+                Some(id) => start_level = Some(id),
+                None => usage(),
+            },
+            "--deny-warnings" => deny_warnings = true,
+            _ if project_path.is_none() && !arg.starts_with("--") => project_path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let project_path = match project_path {
+        Some(project_path) => project_path,
+        None => usage(),
+    };
+    let project_path = Path::new(&project_path);
+
+    // same parsing as the asset loader, images are only checked for on disk
+    let project = std::fs::read(project_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|bytes| LdtkProject::from_bytes(&bytes, project_path, |asset_path| {
+            Handle::weak(HandleId::from(asset_path))
+        }));
+    let project = match project {
+        Ok(project) => project,
+        Err(error) => {
+            println!("error: could not read {}: {}", project_path.display(), error);
+            process::exit(2);
+        }
+    };
+
+    let mut report = Report::default();
+
+    let start_level = start_level.or_else(|| project.project.levels.first().map(|level| level.identifier.clone()));
+    match &start_level {
+        Some(start_level) if !project.project.levels.iter().any(|level| &level.identifier == start_level) => {
+            report.error(format!("start level '{}' does not exist", start_level));
+        },
+        Some(_) => {},
+        None => report.error("project has no levels".to_string()),
+    }
+    let start_level = start_level.unwrap_or_default();
+
+    for tileset in project.project.defs.tilesets.iter() {
+        let image_path = relative_to(project_path, &tileset.rel_path);
+        if !image_path.exists() {
+            report.error(format!("image '{}' of tileset '{}' does not exist", image_path.display(), tileset.identifier));
+        }
+    }
+
+    for level in project.project.levels.iter() {
+        if let Some(rel_path) = &level.bg_rel_path {
+            let image_path = relative_to(project_path, rel_path);
+            if !image_path.exists() {
+                report.error(format!("background image '{}' of level '{}' does not exist", image_path.display(), level.identifier));
+            }
+        }
+
+        if level.layer_instances.is_some() {
+            check_level(&project, level, &player_id, &start_level, &mut report);
+            continue;
+        }
+
+        // levels saved to separate files, read them like the game would
+        let path = match project.external_levels.get(&level.identifier) {
+            Some(path) => path,
+            None => {
+                report.error(format!("level '{}' has no layers and no external file", level.identifier));
+                continue;
+            }
+        };
+        match std::fs::read(path).map_err(anyhow::Error::from).and_then(|bytes| LdtkLevel::from_bytes(&bytes)) {
+            Ok(external) => check_level(&project, &external.level, &player_id, &start_level, &mut report),
+            Err(error) => report.error(format!("could not read external file '{}' for level '{}': {}", path, level.identifier, error)),
+        }
+    }
+
+    println!("{} errors, {} warnings", report.errors.len(), report.warnings.len());
+    if !report.errors.is_empty() || (deny_warnings && !report.warnings.is_empty()) {
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    println!("usage: ldtk-check <project.ldtk> [--player <entity identifier>] [--start-level <level identifier>] [--deny-warnings]");
+    println!("  the player entity is required in the start level (default: the first level), other levels without it are warnings");
+    process::exit(2);
+}
+
+fn check_level(project: &LdtkProject, level: &Level, player_id: &str, start_level: &str, report: &mut Report) {
+    if let Err(error) = level::level_bg_color(level) {
+        report.error(error.to_string());
+    }
+
+    let mut has_player = false;
+    for layer in level.layer_instances.iter().flatten() {
+        if let Err(error) = level::check_layer(project, level, layer) {
+            report.error(error.to_string());
+            continue;
+        }
+
+        has_player |= layer.entity_instances.iter().any(|entity| entity.identifier == player_id);
+
+        // tiles without a collider set up in LDtk (or the DirtBrick table) become full squares
+        let tileset_colliders = layer.tileset_def_uid.and_then(|uid| project.tile_colliders.get(&uid));
+        let default_squares: BTreeSet<i64> = layer.grid_tiles
+            .iter()
+            .chain(layer.auto_layer_tiles.iter())
+            .filter(|tile| level::find_tile_collider(tileset_colliders, tile.t as i32).is_none())
+            .map(|tile| tile.t)
+            .collect();
+        if !default_squares.is_empty() {
+            let tile_ids: Vec<String> = default_squares.iter().map(|id| id.to_string()).collect();
+            report.warning(format!(
+                "layer '{}' in level '{}' uses tiles without a collider, they become squares: {}",
+                layer.identifier, level.identifier, tile_ids.join(", "),
+            ));
+        }
+    }
+
+    if !has_player {
+        let message = format!("level '{}' has no {} entity", level.identifier, player_id);
+        if level.identifier == start_level {
+            report.error(message);
+        } else {
+            report.warning(message);
+        }
+    }
+}
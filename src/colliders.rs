@@ -0,0 +1,238 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::level::{build_collider_shape, TileColliderType};
+
+// Collider of a single grid cell, (0, 0) is the top left cell of the layer
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColliderCell {
+    pub x: i32,
+    pub y: i32,
+    pub collider_type: TileColliderType,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+// Rectangle of grid cells, (0, 0) is the top left cell of the layer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CellRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+// Greedily merges filled cells into rectangles. Each rectangle is grown to the
+// right as far as possible first, then downwards while the whole row is filled.
+pub fn merge_cells(width: i32, height: i32, filled: &[bool]) -> Vec<CellRect> {
+    let mut used = vec![false; filled.len()];
+    let free = |used: &[bool], x: i32, y: i32| {
+        let i = (y * width + x) as usize;
+        filled[i] && !used[i]
+    };
+
+    let mut rects = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            if !free(&used, x, y) {
+                continue;
+            }
+
+            let mut rect_width = 1;
+            while x + rect_width < width && free(&used, x + rect_width, y) {
+                rect_width += 1;
+            }
+
+            let mut rect_height = 1;
+            while y + rect_height < height
+                && (x..x + rect_width).all(|cx| free(&used, cx, y + rect_height))
+            {
+                rect_height += 1;
+            }
+
+            for cy in y..y + rect_height {
+                for cx in x..x + rect_width {
+                    used[(cy * width + cx) as usize] = true;
+                }
+            }
+
+            rects.push(CellRect { x, y, width: rect_width, height: rect_height });
+        }
+    }
+    rects
+}
+
+// One collider per cell of a layer (row by row), a full square wins over
+// anything else stacked on it. Cells outside of the layer are dropped.
+pub fn collider_grid(cells: &[ColliderCell], width: i32, height: i32) -> Vec<Option<ColliderCell>> {
+    let mut grid: Vec<Option<ColliderCell>> = vec![None; (width * height) as usize];
+    for cell in cells.iter() {
+        if cell.x < 0 || cell.y < 0 || cell.x >= width || cell.y >= height {
+            continue;
+        }
+        let slot = &mut grid[(cell.y * width + cell.x) as usize];
+        if cell.collider_type != TileColliderType::None
+            && slot.as_ref().map_or(true, |existing| existing.collider_type != TileColliderType::Square)
+        {
+            *slot = Some(cell.clone());
+        }
+    }
+    grid
+}
+
+// Bakes the colliders of a layer into one compound shape per collision group
+// (solid, one-way and hazard). Squares are merged into rectangles so there are
+// no internal edges for rays to snag on, other shapes are kept per cell.
+// `origin` is the world position of the top left corner of the layer, `physics_scale`
+// the number of world pixels per physics metre.
+pub fn bake_colliders(
+    cells: &[ColliderCell],
+    width: i32,
+    height: i32,
+    tile_size: f32,
+    origin: Vec2,
+    physics_scale: f32,
+) -> Vec<(TileColliderType, ColliderShape)> {
+    let grid = collider_grid(cells, width, height);
+
+    let to_physics = |x: f32, y: f32| {
+        let world = origin + Vec2::new(x * tile_size, -y * tile_size);
+        Isometry::translation(world.x / physics_scale, world.y / physics_scale)
+    };
+
+    let mut baked = Vec::new();
+    for group in [TileColliderType::Square, TileColliderType::OneWay, TileColliderType::Hazard] {
+        let mut parts = Vec::new();
+
+        let filled: Vec<bool> = grid
+            .iter()
+            .map(|cell| matches!(cell, Some(cell) if cell.collider_type == group))
+            .collect();
+        for rect in merge_cells(width, height, &filled) {
+            let half_extents = Vec2::new(rect.width as f32, rect.height as f32) * tile_size / physics_scale / 2.0;
+            parts.push((
+                to_physics(rect.x as f32 + rect.width as f32 / 2.0, rect.y as f32 + rect.height as f32 / 2.0),
+                ColliderShape::cuboid(half_extents.x, half_extents.y),
+            ));
+        }
+
+        // slopes and polygons can't be merged, they go into the solid group as they are
+        if group == TileColliderType::Square {
+            let unmergeable = grid.iter().flatten().filter(|cell| {
+                cell.collider_type.is_slope() || matches!(cell.collider_type, TileColliderType::Polygon(_))
+            });
+            for cell in unmergeable {
+                let position = to_physics(cell.x as f32 + 0.5, cell.y as f32 + 0.5);
+                let shape = build_collider_shape(&cell.collider_type, tile_size / physics_scale, cell.flip_x, cell.flip_y);
+                // polygons are split into convex parts, parry doesn't allow a compound inside a compound
+                match shape.as_compound() {
+                    Some(compound) => {
+                        for (part_position, part) in compound.shapes() {
+                            parts.push((position * part_position, part.clone()));
+                        }
+                    },
+                    None => parts.push((position, shape)),
+                }
+            }
+        }
+
+        if !parts.is_empty() {
+            baked.push((group, ColliderShape::compound(parts)));
+        }
+    }
+    baked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(x: i32, y: i32, collider_type: TileColliderType) -> ColliderCell {
+        ColliderCell { x, y, collider_type, flip_x: false, flip_y: false }
+    }
+
+    // rows of '#' (filled) and '.' (empty)
+    fn filled(rows: &[&str]) -> (i32, i32, Vec<bool>) {
+        let cells = rows.iter().flat_map(|row| row.chars().map(|c| c == '#')).collect();
+        (rows[0].len() as i32, rows.len() as i32, cells)
+    }
+
+    fn rect(x: i32, y: i32, width: i32, height: i32) -> CellRect {
+        CellRect { x, y, width, height }
+    }
+
+    #[test]
+    fn merge_cells_table() {
+        let cases: &[(&str, &[&str], Vec<CellRect>)] = &[
+            ("l shape", &["#..", "#..", "###"], vec![rect(0, 0, 1, 3), rect(1, 2, 2, 1)]),
+            (
+                "hole",
+                &["###", "#.#", "###"],
+                vec![rect(0, 0, 3, 1), rect(0, 1, 1, 2), rect(2, 1, 1, 2), rect(1, 2, 1, 1)],
+            ),
+            ("full row", &["####", "...."], vec![rect(0, 0, 4, 1)]),
+            ("full layer", &["###", "###"], vec![rect(0, 0, 3, 2)]),
+            ("empty", &["...", "..."], vec![]),
+        ];
+
+        for (name, rows, expected) in cases {
+            let (width, height, cells) = filled(rows);
+            let rects = merge_cells(width, height, &cells);
+            assert_eq!(&rects, expected, "{}", name);
+
+            // every filled cell is covered exactly once
+            let mut covered = vec![0; cells.len()];
+            for r in rects.iter() {
+                for y in r.y..r.y + r.height {
+                    for x in r.x..r.x + r.width {
+                        covered[(y * width + x) as usize] += 1;
+                    }
+                }
+            }
+            let expected_cover: Vec<i32> = cells.iter().map(|&c| c as i32).collect();
+            assert_eq!(covered, expected_cover, "{}", name);
+        }
+    }
+
+    #[test]
+    fn collider_grid_table() {
+        let square = || TileColliderType::Square;
+        let slope = || TileColliderType::Slope;
+        let cases = [
+            ("slope then square", vec![cell(0, 0, slope()), cell(0, 0, square())], Some(square())),
+            ("square then slope", vec![cell(0, 0, square()), cell(0, 0, slope())], Some(square())),
+            ("later non-square wins", vec![cell(0, 0, slope()), cell(0, 0, TileColliderType::OneWay)], Some(TileColliderType::OneWay)),
+            ("none is ignored", vec![cell(0, 0, slope()), cell(0, 0, TileColliderType::None)], Some(slope())),
+            ("outside the layer", vec![cell(-1, 0, square()), cell(2, 0, square()), cell(0, 1, square())], None),
+        ];
+
+        for (name, cells, expected) in cases {
+            let grid = collider_grid(&cells, 2, 1);
+            assert_eq!(grid.len(), 2, "{}", name);
+            assert_eq!(grid[0].as_ref().map(|c| c.collider_type.clone()), expected, "{}", name);
+            assert_eq!(grid[1], None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn polygon_cell_bakes_into_flat_compound() {
+        // concave L shape, convex_decomposition turns it into several parts
+        let l_shape = TileColliderType::Polygon(vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(0.5, 0.0),
+            Vec2::new(0.5, 0.5),
+            Vec2::new(1.0, 0.5),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(0.0, 1.0),
+        ]);
+        let cells = [cell(1, 0, l_shape), cell(0, 0, TileColliderType::Square)];
+        let baked = bake_colliders(&cells, 2, 1, 16.0, Vec2::ZERO, 16.0);
+
+        assert_eq!(baked.len(), 1);
+        assert_eq!(baked[0].0, TileColliderType::Square);
+        let compound = baked[0].1.as_compound().unwrap();
+        // the square plus at least two convex parts of the polygon
+        assert!(compound.shapes().len() >= 3);
+        assert!(compound.shapes().iter().all(|(_, part)| part.as_compound().is_none()));
+    }
+}
@@ -0,0 +1,193 @@
+use bevy::prelude::*;
+use ldtk_rust::{LayerInstance, Level};
+
+use crate::scale::WorldScale;
+
+// Converts between the coordinate spaces of a spawned level:
+// - LDtk pixels: (0, 0) in the top left of the level, Y pointing down
+// - grid cells: LDtk pixels divided by the grid size
+// - world: Bevy pixels, Y pointing up, the level centered on the screen (or at
+//   its LDtk world position when levels are laid out as a world)
+// - physics: world divided by the physics scale
+// Built per level by load_level, found on the LevelRoot and as a resource for
+// the level that is active (or that the player is in).
+#[derive(Component, Clone, Copy, Debug)]
+pub struct LevelCoords {
+    // World position of the top left corner of the level (or layer)
+    pub origin: Vec2,
+    // LDtk pixels per grid cell
+    pub grid_size: f32,
+    pub scale: WorldScale,
+}
+
+impl LevelCoords {
+    // `world_position` is where the level goes in the world (see
+    // LdtkProject::world_position), without one the level is centered
+    pub fn new(level: &Level, grid_size: i64, world_position: Option<IVec2>, scale: WorldScale) -> Self {
+        if let Some(world_position) = world_position {
+            Self::in_world(world_position.as_vec2(), grid_size as f32, scale)
+        } else {
+            Self::centered(Vec2::new(level.px_wid as f32, level.px_hei as f32), grid_size as f32, scale)
+        }
+    }
+
+    // Level centered on the screen. Centered on the level's pixel size rather than
+    // each layer's c_wid * grid_size like convert_to_world did, so layers whose grid
+    // doesn't divide the level size line up with the others instead of shifting.
+    pub fn centered(level_size_px: Vec2, grid_size: f32, scale: WorldScale) -> Self {
+        Self {
+            origin: Vec2::new(-level_size_px.x, level_size_px.y) * scale.tile_scale / 2.0,
+            grid_size,
+            scale,
+        }
+    }
+
+    // Level at its LDtk world position (worldX, worldY in LDtk pixels)
+    pub fn in_world(world_px: Vec2, grid_size: f32, scale: WorldScale) -> Self {
+        Self {
+            origin: Vec2::new(world_px.x, -world_px.y) * scale.tile_scale,
+            grid_size,
+            scale,
+        }
+    }
+
+    // Coordinates of a layer of the level, with the layer's offset and grid size
+    pub fn for_layer(&self, layer: &LayerInstance) -> Self {
+        let offset = Vec2::new(layer.px_total_offset_x as f32, layer.px_total_offset_y as f32);
+        Self {
+            origin: self.ldtk_px_to_world(offset),
+            grid_size: layer.grid_size as f32,
+            scale: self.scale,
+        }
+    }
+
+    // Size of a grid cell in world pixels
+    pub fn cell_size(&self) -> f32 {
+        self.grid_size * self.scale.tile_scale
+    }
+
+    pub fn ldtk_px_to_world(&self, px: Vec2) -> Vec2 {
+        self.origin + Vec2::new(px.x, -px.y) * self.scale.tile_scale
+    }
+
+    pub fn world_to_ldtk_px(&self, world: Vec2) -> Vec2 {
+        let px = (world - self.origin) / self.scale.tile_scale;
+        Vec2::new(px.x, -px.y)
+    }
+
+    // Center of the cell
+    pub fn grid_to_world(&self, cell: IVec2) -> Vec2 {
+        self.ldtk_px_to_world((cell.as_vec2() + Vec2::splat(0.5)) * self.grid_size)
+    }
+
+    // Cell containing the point, cells outside of the level are returned as well
+    pub fn world_to_grid(&self, world: Vec2) -> IVec2 {
+        (self.world_to_ldtk_px(world) / self.grid_size).floor().as_ivec2()
+    }
+
+    pub fn world_to_physics(&self, world: Vec2) -> Vec2 {
+        world / self.scale.physics_scale
+    }
+
+    pub fn physics_to_world(&self, physics: Vec2) -> Vec2 {
+        physics * self.scale.physics_scale
+    }
+
+    pub fn grid_to_physics(&self, cell: IVec2) -> Vec2 {
+        self.world_to_physics(self.grid_to_world(cell))
+    }
+
+    pub fn physics_to_grid(&self, physics: Vec2) -> IVec2 {
+        self.world_to_grid(self.physics_to_world(physics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scale() -> WorldScale {
+        WorldScale {
+            tile_scale: 3.0,
+            physics_scale: 48.0,
+        }
+    }
+
+    fn levels() -> Vec<LevelCoords> {
+        vec![
+            LevelCoords::centered(Vec2::new(320.0, 176.0), 16.0, scale()),
+            LevelCoords::in_world(Vec2::new(512.0, -256.0), 16.0, scale()),
+        ]
+    }
+
+    fn assert_close(a: Vec2, b: Vec2) {
+        assert!(a.abs_diff_eq(b, 1e-3), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn grid_world_round_trip() {
+        for coords in levels() {
+            for cell in [IVec2::new(0, 0), IVec2::new(3, 7), IVec2::new(19, 10), IVec2::new(-2, -1)] {
+                assert_eq!(coords.world_to_grid(coords.grid_to_world(cell)), cell);
+            }
+        }
+    }
+
+    #[test]
+    fn ldtk_px_world_round_trip() {
+        for coords in levels() {
+            for px in [Vec2::ZERO, Vec2::new(5.5, 12.0), Vec2::new(320.0, 176.0), Vec2::new(-8.0, 40.0)] {
+                assert_close(coords.world_to_ldtk_px(coords.ldtk_px_to_world(px)), px);
+            }
+        }
+    }
+
+    #[test]
+    fn world_physics_round_trip() {
+        for coords in levels() {
+            for world in [Vec2::ZERO, Vec2::new(120.0, -36.0), Vec2::new(-480.0, 264.0)] {
+                assert_close(coords.physics_to_world(coords.world_to_physics(world)), world);
+            }
+            for cell in [IVec2::new(0, 0), IVec2::new(4, 9)] {
+                assert_eq!(coords.physics_to_grid(coords.grid_to_physics(cell)), cell);
+            }
+        }
+    }
+
+    #[test]
+    fn y_points_up_in_world() {
+        for coords in levels() {
+            let top = coords.ldtk_px_to_world(Vec2::new(0.0, 0.0));
+            let below = coords.ldtk_px_to_world(Vec2::new(0.0, 16.0));
+            assert!(below.y < top.y);
+            assert_close(top - below, Vec2::new(0.0, 16.0 * scale().tile_scale));
+        }
+    }
+
+    #[test]
+    fn centered_level_is_centered_on_its_pixel_size() {
+        // 100x60 isn't a multiple of the 16 px grid, the center still ends up at 0, 0
+        let coords = LevelCoords::centered(Vec2::new(100.0, 60.0), 16.0, scale());
+        assert_close(coords.ldtk_px_to_world(Vec2::new(50.0, 30.0)), Vec2::ZERO);
+        assert_close(coords.origin, Vec2::new(-150.0, 90.0));
+    }
+
+    #[test]
+    fn in_world_level_starts_at_its_world_position() {
+        let coords = LevelCoords::in_world(Vec2::new(512.0, -256.0), 16.0, scale());
+        assert_close(coords.ldtk_px_to_world(Vec2::ZERO), Vec2::new(1536.0, 768.0));
+        assert_eq!(coords.world_to_grid(Vec2::new(1536.0 + 1.0, 768.0 - 1.0)), IVec2::new(0, 0));
+    }
+
+    #[test]
+    fn layer_offset_moves_the_origin() {
+        let coords = LevelCoords::centered(Vec2::new(320.0, 176.0), 16.0, scale());
+        let layer = LevelCoords {
+            origin: coords.ldtk_px_to_world(Vec2::new(8.0, 8.0)),
+            grid_size: 8.0,
+            scale: coords.scale,
+        };
+        assert_close(layer.grid_to_world(IVec2::ZERO), coords.ldtk_px_to_world(Vec2::new(12.0, 12.0)));
+        assert_eq!(layer.cell_size(), 24.0);
+    }
+}
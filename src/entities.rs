@@ -0,0 +1,75 @@
+use bevy::prelude::*;
+use ldtk_rust::FieldInstance;
+use std::collections::HashMap;
+
+use crate::fields::{FieldsError, FromLdtkFields};
+use crate::scale::WorldScale;
+
+// Everything a spawner needs to know about an LDtk entity instance.
+// Positions and sizes are in world pixels, with Y pointing up.
+pub struct EntitySpawnInfo<'a> {
+    pub identifier: &'a str,
+    pub position: Vec3,
+    pub size: Vec2,
+    pub pivot: Vec2,
+    pub fields: &'a [FieldInstance],
+    // Scale the level was spawned at, divide by physics_scale for physics positions
+    pub scale: WorldScale,
+}
+
+impl EntitySpawnInfo<'_> {
+    // LDtk places entities by their pivot (top left is 0,0), this gives the center
+    pub fn center(&self) -> Vec2 {
+        Vec2::new(
+            self.position.x + (0.5 - self.pivot.x) * self.size.x,
+            self.position.y - (0.5 - self.pivot.y) * self.size.y,
+        )
+    }
+
+    // The entity's fields deserialized into T, see FromLdtkFields
+    pub fn fields_as<T: FromLdtkFields>(&self) -> Result<T, FieldsError> {
+        T::from_ldtk_fields(self.fields)
+    }
+}
+
+type EntitySpawner = Box<dyn Fn(&mut Commands, &EntitySpawnInfo) -> Entity + Send + Sync>;
+
+// Maps LDtk entity identifiers to the functions that spawn them
+#[derive(Default)]
+pub struct EntitySpawnRegistry {
+    spawners: HashMap<String, EntitySpawner>,
+}
+
+impl EntitySpawnRegistry {
+    pub fn register<F>(&mut self, identifier: &str, spawner: F)
+    where
+        F: Fn(&mut Commands, &EntitySpawnInfo) -> Entity + Send + Sync + 'static,
+    {
+        self.spawners.insert(identifier.to_string(), Box::new(spawner));
+    }
+
+    // Like register, and inserts a C built from the entity's fields into whatever
+    // the spawner spawned. Fields that don't fit C are reported and C is left out.
+    pub fn register_with_fields<C, F>(&mut self, identifier: &str, spawner: F)
+    where
+        C: Component + FromLdtkFields,
+        F: Fn(&mut Commands, &EntitySpawnInfo) -> Entity + Send + Sync + 'static,
+    {
+        self.register(identifier, move |commands, info| {
+            let entity = spawner(commands, info);
+            match info.fields_as::<C>() {
+                Ok(component) => {
+                    commands.entity(entity).insert(component);
+                },
+                Err(error) => println!("Could not read fields of entity {}: {}", info.identifier, error),
+            }
+            entity
+        });
+    }
+
+    pub fn spawn(&self, commands: &mut Commands, info: &EntitySpawnInfo) -> Option<Entity> {
+        self.spawners
+            .get(info.identifier)
+            .map(|spawner| spawner(commands, info))
+    }
+}
@@ -0,0 +1,260 @@
+use bevy::prelude::*;
+use ldtk_rust::FieldInstance;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, Visitor};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+// Value of an LDtk field instance
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    // Optional field without a value
+    Null,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    // String, Multilines and FilePath fields
+    String(String),
+    Color(Color),
+    // Enum value, without the enum name
+    Enum(String),
+    // Grid cell, (0, 0) is the top left cell of the level
+    Point(IVec2),
+    EntityRef { entity_iid: String, level_iid: String },
+    Array(Vec<FieldValue>),
+}
+
+impl FieldValue {
+    // Parses `__value` according to `__type`, None if they don't match up
+    pub fn parse(field_type: &str, value: &Value) -> Option<Self> {
+        if value.is_null() {
+            return Some(FieldValue::Null);
+        }
+
+        if let Some(item_type) = field_type.strip_prefix("Array<").and_then(|t| t.strip_suffix('>')) {
+            return value
+                .as_array()?
+                .iter()
+                .map(|item| FieldValue::parse(item_type, item))
+                .collect::<Option<Vec<_>>>()
+                .map(FieldValue::Array);
+        }
+
+        match field_type {
+            "Int" => value.as_i64().map(FieldValue::Int),
+            "Float" => value.as_f64().map(FieldValue::Float),
+            "Bool" => value.as_bool().map(FieldValue::Bool),
+            "String" | "Multilines" | "FilePath" => value.as_str().map(|s| FieldValue::String(s.to_string())),
+            "Color" => value
+                .as_str()
+                .and_then(|s| s.strip_prefix('#'))
+                .and_then(|hex| Color::hex(hex).ok())
+                .map(FieldValue::Color),
+            "Point" => Some(FieldValue::Point(IVec2::new(
+                value.get("cx")?.as_i64()? as i32,
+                value.get("cy")?.as_i64()? as i32,
+            ))),
+            "EntityRef" => Some(FieldValue::EntityRef {
+                entity_iid: value.get("entityIid")?.as_str()?.to_string(),
+                level_iid: value.get("levelIid")?.as_str()?.to_string(),
+            }),
+            _ if field_type.starts_with("LocalEnum.") || field_type.starts_with("ExternalEnum.") => {
+                value.as_str().map(|s| FieldValue::Enum(s.to_string()))
+            },
+            _ => None,
+        }
+    }
+}
+
+// Parses field instances by identifier. Fields that can't be parsed are left out.
+pub fn parse_fields(fields: &[FieldInstance]) -> HashMap<String, FieldValue> {
+    let mut values = HashMap::new();
+    for field in fields.iter() {
+        let value = field.value.as_ref().unwrap_or(&Value::Null);
+        match FieldValue::parse(&field.field_instance_type, value) {
+            Some(parsed) => {
+                values.insert(field.identifier.clone(), parsed);
+            },
+            None => println!("Could not read field {} of type {}", field.identifier, field.field_instance_type),
+        }
+    }
+    values
+}
+
+// Fields of the level that is currently spawned (or that the player is in when
+// levels are streamed in), so designers can tune things per level in LDtk
+#[derive(Default)]
+pub struct LevelSettings {
+    pub level_id: String,
+    pub fields: HashMap<String, FieldValue>,
+}
+
+impl LevelSettings {
+    pub fn from_fields(level_id: &str, fields: &[FieldInstance]) -> Self {
+        Self {
+            level_id: level_id.to_string(),
+            fields: parse_fields(fields),
+        }
+    }
+
+    pub fn get(&self, identifier: &str) -> Option<&FieldValue> {
+        self.fields.get(identifier)
+    }
+
+    pub fn get_int(&self, identifier: &str) -> Option<i64> {
+        match self.get(identifier)? {
+            FieldValue::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    // Int fields are accepted as well
+    pub fn get_float(&self, identifier: &str) -> Option<f32> {
+        match self.get(identifier)? {
+            FieldValue::Float(value) => Some(*value as f32),
+            FieldValue::Int(value) => Some(*value as f32),
+            _ => None,
+        }
+    }
+
+    pub fn get_bool(&self, identifier: &str) -> Option<bool> {
+        match self.get(identifier)? {
+            FieldValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_string(&self, identifier: &str) -> Option<&str> {
+        match self.get(identifier)? {
+            FieldValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_color(&self, identifier: &str) -> Option<Color> {
+        match self.get(identifier)? {
+            FieldValue::Color(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_enum(&self, identifier: &str) -> Option<&str> {
+        match self.get(identifier)? {
+            FieldValue::Enum(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_point(&self, identifier: &str) -> Option<IVec2> {
+        match self.get(identifier)? {
+            FieldValue::Point(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    // Iid of the referenced entity
+    pub fn get_entity_ref(&self, identifier: &str) -> Option<&str> {
+        match self.get(identifier)? {
+            FieldValue::EntityRef { entity_iid, .. } => Some(entity_iid),
+            _ => None,
+        }
+    }
+}
+
+// Builds a value (usually a component) from the field instances of an LDtk
+// entity. Implemented for everything serde can deserialize: field identifiers
+// map to struct fields (use #[serde(rename)] when they differ), fields that
+// aren't in the struct are ignored. Points become [cx, cy] arrays.
+pub trait FromLdtkFields: Sized {
+    fn from_ldtk_fields(fields: &[FieldInstance]) -> Result<Self, FieldsError>;
+}
+
+impl<T: DeserializeOwned> FromLdtkFields for T {
+    fn from_ldtk_fields(fields: &[FieldInstance]) -> Result<Self, FieldsError> {
+        T::deserialize(FieldsDeserializer { fields })
+            .map_err(|error| FieldsError(format!("{}: {}", std::any::type_name::<T>(), error)))
+    }
+}
+
+// Why fields didn't fit, e.g.
+// "game::MovingPlatform: field 'speed' of type String: invalid type: string "fast", expected f32"
+#[derive(Debug)]
+pub struct FieldsError(String);
+
+impl fmt::Display for FieldsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for FieldsError {}
+
+impl de::Error for FieldsError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        FieldsError(msg.to_string())
+    }
+}
+
+// Presents field instances to serde as a map from identifier to value
+struct FieldsDeserializer<'a> {
+    fields: &'a [FieldInstance],
+}
+
+impl<'de, 'a> de::Deserializer<'de> for FieldsDeserializer<'a> {
+    type Error = FieldsError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FieldsError> {
+        visitor.visit_map(FieldsAccess { fields: self.fields.iter(), current: None })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct FieldsAccess<'a> {
+    fields: std::slice::Iter<'a, FieldInstance>,
+    current: Option<&'a FieldInstance>,
+}
+
+impl<'de, 'a> MapAccess<'de> for FieldsAccess<'a> {
+    type Error = FieldsError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, FieldsError> {
+        match self.fields.next() {
+            Some(field) => {
+                self.current = Some(field);
+                seed.deserialize(field.identifier.as_str().into_deserializer()).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, FieldsError> {
+        let field = self.current
+            .take()
+            .ok_or_else(|| FieldsError("field value read before its identifier".to_string()))?;
+        // errors name the field, serde_json alone would only say what didn't fit
+        seed.deserialize(field_json(&field.field_instance_type, field.value.clone().unwrap_or(Value::Null)))
+            .map_err(|error| FieldsError(format!(
+                "field '{}' of type {}: {}",
+                field.identifier, field.field_instance_type, error,
+            )))
+    }
+}
+
+// Field value in a shape that's easier to deserialize into
+fn field_json(field_type: &str, value: Value) -> Value {
+    match (field_type, value) {
+        ("Point", Value::Object(point)) => match (point.get("cx"), point.get("cy")) {
+            (Some(cx), Some(cy)) => Value::Array(vec![cx.clone(), cy.clone()]),
+            _ => Value::Object(point),
+        },
+        ("Array<Point>", Value::Array(points)) => {
+            Value::Array(points.into_iter().map(|point| field_json("Point", point)).collect())
+        },
+        (_, value) => value,
+    }
+}
@@ -0,0 +1,506 @@
+use bevy::{
+    asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::level::{dirt_brick_colliders, TileCollider, TileColliderType, DIRT_BRICK_TILESET};
+
+#[derive(TypeUuid)]
+#[uuid = "7c0c9a5e-3f0e-4b8f-9d0a-2f3b8c6a1e54"]
+pub struct LdtkProject {
+    pub project: ldtk_rust::Project,
+    pub tileset_images: HashMap<i64, Handle<Image>>,
+    // Tile colliders by tileset uid and tile id, set up in LDtk or hardcoded for DirtBrick
+    pub tile_colliders: HashMap<i64, HashMap<i64, TileCollider>>,
+    // Asset paths of levels saved to separate files, by level identifier.
+    // These are only loaded once the level is requested.
+    pub external_levels: HashMap<String, String>,
+    // Parallax settings of layer definitions, by layer definition uid
+    pub layer_parallax: HashMap<i64, LayerParallax>,
+    // Per-tile alpha of the levels stored in the project file, by level identifier
+    pub tile_alpha: HashMap<String, TileAlpha>,
+    // Background images by level identifier, external levels included
+    pub backgrounds: HashMap<String, LevelBackground>,
+    pub world_layout: WorldLayout,
+    // Position of each level in the world in LDtk pixels, by level identifier.
+    // Linear layouts don't store one, their levels are put next to each other.
+    pub level_positions: HashMap<String, IVec2>,
+}
+
+// How levels are arranged in the project
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WorldLayout {
+    Free,
+    GridVania,
+    LinearHorizontal,
+    LinearVertical,
+}
+
+impl WorldLayout {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Free" => Some(WorldLayout::Free),
+            "GridVania" => Some(WorldLayout::GridVania),
+            "LinearHorizontal" => Some(WorldLayout::LinearHorizontal),
+            "LinearVertical" => Some(WorldLayout::LinearVertical),
+            _ => None,
+        }
+    }
+
+    pub fn is_linear(&self) -> bool {
+        matches!(self, WorldLayout::LinearHorizontal | WorldLayout::LinearVertical)
+    }
+}
+
+// A level saved to its own .ldtkl file
+#[derive(TypeUuid)]
+#[uuid = "2d4e8f1a-6b3c-4a7e-8c5d-9e0f1a2b3c4d"]
+pub struct LdtkLevel {
+    pub level: ldtk_rust::Level,
+    pub tile_alpha: TileAlpha,
+}
+
+#[derive(Clone, Copy)]
+pub struct LayerParallax {
+    // 0 scrolls with the level, 1 stays fixed on screen
+    pub factor: Vec2,
+    // Scale the layer down the further away it is
+    pub scaling: bool,
+}
+
+// How a level's background image is fitted into the level
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BackgroundFit {
+    Unscaled,
+    Contain,
+    Cover,
+    // Stretched to the level without keeping the aspect ratio
+    CoverDirty,
+    Repeat,
+}
+
+impl BackgroundFit {
+    pub fn from_name(name: &str) -> Option<BackgroundFit> {
+        match name {
+            "Unscaled" => Some(BackgroundFit::Unscaled),
+            "Contain" => Some(BackgroundFit::Contain),
+            "Cover" => Some(BackgroundFit::Cover),
+            "CoverDirty" => Some(BackgroundFit::CoverDirty),
+            "Repeat" => Some(BackgroundFit::Repeat),
+            _ => None,
+        }
+    }
+}
+
+// Background image of a level. Read from the json directly, ldtk_rust doesn't
+// know the Repeat fit mode.
+#[derive(Clone)]
+pub struct LevelBackground {
+    pub image: Handle<Image>,
+    pub fit: BackgroundFit,
+    // Where the image is anchored, (0, 0) is the top left of the level and (1, 1) the bottom right
+    pub pivot: Vec2,
+}
+
+// Alpha of the tiles of a level, ldtk_rust doesn't read it. Only tiles that
+// aren't fully opaque are stored.
+#[derive(Default)]
+pub struct TileAlpha {
+    // by layer index and tile index, counting grid tiles first and auto-layer tiles after
+    alpha: HashMap<(usize, usize), f32>,
+}
+
+impl TileAlpha {
+    pub fn get(&self, layer: usize, tile: usize) -> f32 {
+        self.alpha.get(&(layer, tile)).copied().unwrap_or(1.0)
+    }
+
+    fn from_metadata(level: &LevelMetadata) -> Self {
+        let mut alpha = HashMap::new();
+        for (layer_index, layer) in level.layer_instances.iter().flatten().enumerate() {
+            let tiles = layer.grid_tiles.iter().chain(layer.auto_layer_tiles.iter());
+            for (tile_index, tile) in tiles.enumerate() {
+                if tile.a < 1.0 {
+                    alpha.insert((layer_index, tile_index), tile.a);
+                }
+            }
+        }
+        Self { alpha }
+    }
+}
+
+// The parts of the project json that are read directly instead of through ldtk_rust
+#[derive(Deserialize)]
+struct ProjectMetadata {
+    defs: DefinitionsMetadata,
+    levels: Vec<LevelMetadata>,
+    // null in projects with multiple worlds
+    #[serde(rename = "worldLayout", default)]
+    world_layout: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DefinitionsMetadata {
+    layers: Vec<LayerDefMetadata>,
+    tilesets: Vec<TilesetMetadata>,
+}
+
+// Parallax was added in LDtk 1.0, older projects don't have it
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LayerDefMetadata {
+    uid: i64,
+    #[serde(default)]
+    parallax_factor_x: f32,
+    #[serde(default)]
+    parallax_factor_y: f32,
+    #[serde(default)]
+    parallax_scaling: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LevelMetadata {
+    identifier: String,
+    layer_instances: Option<Vec<LayerInstanceMetadata>>,
+    #[serde(default)]
+    bg_rel_path: Option<String>,
+    #[serde(default)]
+    bg_pos: Option<String>,
+    #[serde(default = "centered")]
+    bg_pivot_x: f32,
+    #[serde(default = "centered")]
+    bg_pivot_y: f32,
+}
+
+fn centered() -> f32 {
+    0.5
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LayerInstanceMetadata {
+    #[serde(default)]
+    grid_tiles: Vec<TileMetadata>,
+    #[serde(default)]
+    auto_layer_tiles: Vec<TileMetadata>,
+}
+
+// Tile alpha was added in LDtk 1.3
+#[derive(Deserialize)]
+struct TileMetadata {
+    #[serde(default = "full_alpha")]
+    a: f32,
+}
+
+fn full_alpha() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TilesetMetadata {
+    uid: i64,
+    identifier: String,
+    tile_grid_size: i64,
+    #[serde(default)]
+    enum_tags: Vec<EnumTag>,
+    #[serde(default)]
+    custom_data: Vec<TileCustomData>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EnumTag {
+    enum_value_id: String,
+    tile_ids: Vec<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TileCustomData {
+    tile_id: i64,
+    data: String,
+}
+
+// Collider given in the custom data of a tile, e.g.
+// {"collider": "Slope_FlipX"} or {"collider": "Polygon", "points": [[0, 16], [16, 16], [16, 8]]}
+#[derive(Deserialize)]
+struct ColliderCustomData {
+    collider: String,
+    #[serde(default)]
+    points: Vec<[f32; 2]>,
+}
+
+impl LdtkProject {
+    // Parses a project file. `load_image` gives the handle for a tileset or
+    // background image from its asset path, which is relative to the project at
+    // `project_path`.
+    pub fn from_bytes(
+        bytes: &[u8],
+        project_path: &Path,
+        mut load_image: impl FnMut(AssetPath<'static>) -> Handle<Image>,
+    ) -> Result<Self, anyhow::Error> {
+        let project: ldtk_rust::Project = serde_json::from_slice(bytes)?;
+        let metadata: ProjectMetadata = serde_json::from_slice(bytes)?;
+        let mut tile_colliders = HashMap::new();
+        for tileset in metadata.defs.tilesets.iter() {
+            let ldtk_colliders = tileset_colliders(tileset);
+            // the DirtBrick slopes from before colliders could be set up in LDtk, LDtk wins
+            let mut colliders = if tileset.identifier == DIRT_BRICK_TILESET {
+                dirt_brick_colliders()
+            } else {
+                HashMap::new()
+            };
+            colliders.extend(ldtk_colliders);
+            tile_colliders.insert(tileset.uid, colliders);
+        }
+
+        let tileset_images = project.defs.tilesets
+            .iter()
+            .map(|tileset| {
+                let asset_path = AssetPath::new(relative_to(project_path, &tileset.rel_path), None);
+                (tileset.uid, load_image(asset_path))
+            })
+            .collect();
+
+        let external_levels = project.levels
+            .iter()
+            .filter_map(|level| {
+                let rel_path = level.external_rel_path.as_ref()?;
+                let path = relative_to(project_path, rel_path);
+                Some((level.identifier.clone(), path.to_string_lossy().into_owned()))
+            })
+            .collect();
+
+        let layer_parallax = metadata.defs.layers
+            .iter()
+            .map(|layer| (layer.uid, LayerParallax {
+                factor: Vec2::new(layer.parallax_factor_x, layer.parallax_factor_y),
+                scaling: layer.parallax_scaling,
+            }))
+            .collect();
+        let tile_alpha = metadata.levels
+            .iter()
+            .map(|level| (level.identifier.clone(), TileAlpha::from_metadata(level)))
+            .collect();
+        let backgrounds = metadata.levels
+            .iter()
+            .filter_map(|level| {
+                let rel_path = level.bg_rel_path.as_ref()?;
+                let fit = level.bg_pos.as_deref().and_then(BackgroundFit::from_name).unwrap_or_else(|| {
+                    println!("Unknown background fit {:?} in level {}, drawing it unscaled", level.bg_pos, level.identifier);
+                    BackgroundFit::Unscaled
+                });
+                let asset_path = AssetPath::new(relative_to(project_path, rel_path), None);
+                Some((level.identifier.clone(), LevelBackground {
+                    image: load_image(asset_path),
+                    fit,
+                    pivot: Vec2::new(level.bg_pivot_x, level.bg_pivot_y),
+                }))
+            })
+            .collect();
+
+        let world_layout = metadata.world_layout.as_deref().and_then(WorldLayout::from_name).unwrap_or_else(|| {
+            println!("Unknown world layout {:?}, using the level positions as they are", metadata.world_layout);
+            WorldLayout::Free
+        });
+        let level_positions = level_positions(world_layout, &project.levels);
+
+        Ok(LdtkProject {
+            project,
+            tileset_images,
+            tile_colliders,
+            external_levels,
+            layer_parallax,
+            tile_alpha,
+            backgrounds,
+            world_layout,
+            level_positions,
+        })
+    }
+
+    // Top left corner of the level in the world, in LDtk pixels
+    pub fn world_position(&self, level: &ldtk_rust::Level) -> IVec2 {
+        self.level_positions
+            .get(&level.identifier)
+            .copied()
+            .unwrap_or_else(|| IVec2::new(level.world_x as i32, level.world_y as i32))
+    }
+}
+
+// Linear layouts leave worldX and worldY at -1, the levels go one after the
+// other in the order they're listed in
+fn level_positions(layout: WorldLayout, levels: &[ldtk_rust::Level]) -> HashMap<String, IVec2> {
+    let mut next = IVec2::ZERO;
+    levels
+        .iter()
+        .map(|level| {
+            let position = match layout {
+                WorldLayout::Free | WorldLayout::GridVania => IVec2::new(level.world_x as i32, level.world_y as i32),
+                WorldLayout::LinearHorizontal => {
+                    let position = next;
+                    next.x += level.px_wid as i32;
+                    position
+                },
+                WorldLayout::LinearVertical => {
+                    let position = next;
+                    next.y += level.px_hei as i32;
+                    position
+                },
+            };
+            (level.identifier.clone(), position)
+        })
+        .collect()
+}
+
+impl LdtkLevel {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        let level: ldtk_rust::Level = serde_json::from_slice(bytes)?;
+        let metadata: LevelMetadata = serde_json::from_slice(bytes)?;
+        let tile_alpha = TileAlpha::from_metadata(&metadata);
+        Ok(LdtkLevel { level, tile_alpha })
+    }
+}
+
+#[derive(Default)]
+pub struct LdtkLoader;
+
+impl AssetLoader for LdtkLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            // tileset and background images are loaded as dependencies of the project,
+            // so the project only counts as loaded once every image is available
+            let mut dependencies = Vec::new();
+            let project_path = load_context.path().to_path_buf();
+            let project = LdtkProject::from_bytes(bytes, &project_path, |asset_path| {
+                dependencies.push(asset_path.clone());
+                load_context.get_handle(asset_path)
+            })?;
+
+            load_context.set_default_asset(LoadedAsset::new(project).with_dependencies(dependencies));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ldtk"]
+    }
+}
+
+#[derive(Default)]
+pub struct LdtkLevelLoader;
+
+impl AssetLoader for LdtkLevelLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            load_context.set_default_asset(LoadedAsset::new(LdtkLevel::from_bytes(bytes)?));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ldtkl"]
+    }
+}
+
+// Paths in LDtk files are relative to the project file, Bevy wants them
+// relative to the asset folder.
+pub fn relative_to(project_path: &Path, rel_path: &str) -> PathBuf {
+    project_path
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(rel_path)
+}
+
+// Tile colliders come from enum tags on the tileset (values named like the
+// collider types, optionally with a _FlipX, _FlipY or _FlipXY suffix) and from
+// per-tile custom data, custom data wins when both are set.
+fn tileset_colliders(tileset: &TilesetMetadata) -> HashMap<i64, TileCollider> {
+    let mut colliders = HashMap::new();
+
+    for tag in tileset.enum_tags.iter() {
+        if let Some(collider) = parse_collider_name(&tag.enum_value_id) {
+            for tile_id in tag.tile_ids.iter() {
+                colliders.insert(*tile_id, collider.clone());
+            }
+        }
+    }
+
+    for custom_data in tileset.custom_data.iter() {
+        // custom data is free text, anything that isn't a collider is left alone
+        let data: ColliderCustomData = match serde_json::from_str(&custom_data.data) {
+            Ok(data) => data,
+            Err(_) => continue,
+        };
+
+        let grid_size = tileset.tile_grid_size as f32;
+        // polygons need an area for parry to build a shape from, and have to stay inside the tile
+        let valid_polygon = data.points.len() >= 3
+            && polygon_area(&data.points) > grid_size * grid_size * 0.001
+            && data.points.iter().all(|&[x, y]| (0.0..=grid_size).contains(&x) && (0.0..=grid_size).contains(&y));
+        let collider = if data.collider == "Polygon" && valid_polygon {
+            Some(TileCollider {
+                collider_type: TileColliderType::Polygon(
+                    data.points.iter().map(|[x, y]| Vec2::new(x / grid_size, y / grid_size)).collect()
+                ),
+                flip_x: false,
+                flip_y: false,
+            })
+        } else if data.collider == "Polygon" {
+            None
+        } else {
+            parse_collider_name(&data.collider)
+        };
+
+        match collider {
+            Some(collider) => {
+                colliders.insert(custom_data.tile_id, collider);
+            },
+            None => println!("Unknown or invalid collider '{}' on tile {}", data.collider, custom_data.tile_id),
+        }
+    }
+
+    colliders
+}
+
+// Shoelace formula, collinear or repeated points give 0
+fn polygon_area(points: &[[f32; 2]]) -> f32 {
+    let twice_area: f32 = points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|([x1, y1], [x2, y2])| x1 * y2 - x2 * y1)
+        .sum();
+    twice_area.abs() / 2.0
+}
+
+fn parse_collider_name(name: &str) -> Option<TileCollider> {
+    let mut parts = name.split('_');
+    let collider_type = TileColliderType::from_name(parts.next()?)?;
+    let mut collider = TileCollider { collider_type, flip_x: false, flip_y: false };
+    for flip in parts {
+        match flip {
+            "FlipX" => collider.flip_x = true,
+            "FlipY" => collider.flip_y = true,
+            "FlipXY" => {
+                collider.flip_x = true;
+                collider.flip_y = true;
+            },
+            _ => return None,
+        }
+    }
+    Some(collider)
+}
@@ -1,223 +1,1122 @@
-use bevy::prelude::*;
-use ldtk_rust::{LayerInstance, TileInstance};
-use std::collections::HashMap;
-use bevy_rapier2d::prelude::*;
-
-use crate::PHYSICS_SCALE;
-
-const TILE_SCALE: f32 = 2.5;
-
-pub fn load_level(
-    commands: &mut Commands,
-    asset_server: &Res<AssetServer>,
-    texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
-    project_path: &str,
-    level_id: &str
-) {
-    let project = ldtk_rust::Project::load_project(project_path.to_string());
-
-    // load tilesets
-    let mut atlas_handles = HashMap::new();
-    for tileset in project.defs.tilesets.iter() {
-        let texture_handle = asset_server.load(&tileset.rel_path[..]);
-        let texture_atlas_handle = texture_atlases.add(TextureAtlas::from_grid(
-            texture_handle,
-            Vec2::new(tileset.tile_grid_size as f32, tileset.tile_grid_size as f32),
-            (tileset.px_wid / tileset.tile_grid_size) as usize,
-            (tileset.px_hei / tileset.tile_grid_size) as usize,
-        ));
-        atlas_handles.insert(tileset.uid, texture_atlas_handle);
-    }
-    
-    // find level
-    let level = project.levels.iter()
-        .find(|&level| level.identifier == level_id)
-        .unwrap_or_else(|| panic!("level with identifier '{}' does not exist in project", level_id));
-
-    // set bg color
-    commands.insert_resource(ClearColor(
-        Color::hex(&level.bg_color[1..]).expect("invalid __bg_color hex in json data"),
-    ));
-    
-
-    for (z_index, layer) in level.layer_instances
-        .as_ref()
-        .expect("layer instances missing, external levels are not supported")
-        .iter()
-        .enumerate()
-        .rev()
-    {
-        let tileset_uid = layer.tileset_def_uid.unwrap_or(-1);
-        let layer_width = layer.c_wid as f32 * (layer.grid_size as f32 * TILE_SCALE);
-        let layer_height = layer.c_hei as f32 * (layer.grid_size as f32 * TILE_SCALE);
-
-        match &layer.layer_instance_type[..] {
-            "Tiles" => {
-                println!("Spawning Tiles layer: {}", layer.identifier);
-                for tile in layer.grid_tiles.iter() {
-                    spawn_tile(layer, layer_width, layer_height, tile, z_index as f32, commands, atlas_handles[&tileset_uid].clone());
-                }
-            },
-            "AutoLayer" => {
-                println!("Spawning AutoLayer layer: {}", layer.identifier);
-                for tile in layer.auto_layer_tiles.iter() {
-                    spawn_tile(layer, layer_width, layer_height, tile, z_index as f32, commands, atlas_handles[&tileset_uid].clone());
-                }
-            },
-            "Entities" => {
-                println!("Spawning Entities layer: {}", layer.identifier);
-                // for entity in layer.entity_instances.iter() {
-                //     if entity.identifier == "Player" {
-                //         let position = convert_to_world(
-                //             layer_width,
-                //             layer_height,
-                //             layer.grid_size as i32,
-                //             TILE_SCALE,
-                //             entity.px[0] as i32,
-                //             entity.px[1] as i32,
-                //             z_index as f32,
-                //         );
-                //         player::spawn_player(
-                //             commands, 
-                //             Vec2::new(position.x * PHYSICS_SCALE, position.y * PHYSICS_SCALE)
-                //         );
-                //     }
-                // }
-            }
-            _ => {
-                println!("Skipping layer (not implemented): {}", layer.identifier);
-            }
-        }
-    }
-}
-
-fn spawn_tile(
-    layer: &LayerInstance,
-    layer_width: f32,
-    layer_height: f32,
-    tile: &TileInstance,
-    z_index: f32,
-    commands: &mut Commands,
-    atlas_handle: Handle<TextureAtlas>,
-) {
-    let (flip_x, flip_y) = match tile.f {
-        1 => (true, false),
-        2 => (false, true),
-        3 => (true, true),
-        _ => (false, false),
-    };
-
-    let px_pos = convert_to_world(
-        layer_width,
-        layer_height,
-        layer.grid_size as i32,
-        TILE_SCALE,
-        tile.px[0] as i32,
-        tile.px[1] as i32,
-        z_index
-    );
-
-    let tile_size = layer.grid_size as f32 * TILE_SCALE;
-
-    commands
-        .spawn_bundle(SpriteSheetBundle {
-            transform: Transform {
-                translation: px_pos,
-                ..Default::default()
-            },
-            sprite: TextureAtlasSprite {
-                index: tile.t as usize,
-                flip_x,
-                flip_y,
-                custom_size: Some(Vec2::splat(tile_size)),
-                ..Default::default()
-            },
-            texture_atlas: atlas_handle,
-            ..Default::default()
-        })
-        .insert_bundle(ColliderBundle {
-            shape: get_collider_shape(tile.t as i32, tile_size, flip_x, flip_y).into(),
-            position: (px_pos / PHYSICS_SCALE).into(),
-            flags: ColliderFlags {
-                collision_groups: InteractionGroups::new(0b0010, 0b0001),
-                ..Default::default()
-            }.into(),
-            ..Default::default()
-        });
-}
-
-// LDtk provides pixel locations starting in the top left. For Bevy we need to
-// flip the Y axis and offset from the center of the screen.
-fn convert_to_world(
-    width: f32,
-    height: f32,
-    grid_size: i32,
-    scale: f32,
-    x: i32,
-    y: i32,
-    z: f32,
-) -> Vec3 {
-    let world_x = (x as f32 * scale) + (grid_size as f32 * scale / 2.) - (width / 2.);
-    let world_y = -(y as f32 * scale) - (grid_size as f32 * scale / 2.) + (height / 2.);
-    Vec3::new(world_x, world_y, z)
-}
-
-enum TileColliderType {
-    Square,
-    Slope,
-    HalfSlope1,
-    HalfSlope2,
-}
-
-fn get_collider_shape(
-    tile_id: i32,
-    tile_size: f32,
-    flip_x: bool,
-    flip_y: bool,
-) -> ColliderShape {
-    //hardcoded for now
-    let (collider_type, tileset_flip_x, tileset_flip_y) = match tile_id {
-        // grass
-        6 => (TileColliderType::Slope, false, false),
-        7 => (TileColliderType::Slope, true, false),
-        44 => (TileColliderType::HalfSlope1, false, false),
-        45 => (TileColliderType::HalfSlope2, false, false),
-        46 => (TileColliderType::HalfSlope2, true, false),
-        47 => (TileColliderType::HalfSlope1, true, false),
-
-        // stone
-        86 => (TileColliderType::Slope, false, false),
-        87 => (TileColliderType::Slope, true, false),
-        124 => (TileColliderType::HalfSlope1, false, false),
-        125 => (TileColliderType::HalfSlope2, false, false),
-        126 => (TileColliderType::HalfSlope2, true, false),
-        127 => (TileColliderType::HalfSlope1, true, false),
-
-        _ => (TileColliderType::Square, false, false),
-    };
-
-    let physics_size = tile_size / PHYSICS_SCALE;
-
-    let x_flipper = if tileset_flip_x != flip_x { -1.0 } else { 1.0 };
-    let y_flipper = if tileset_flip_y != flip_y { -1.0 } else { 1.0 };
-
-    match collider_type {
-        TileColliderType::Slope => ColliderShape::convex_hull(&[
-            Vec2::new(x_flipper * -physics_size / 2.0, y_flipper * -physics_size / 2.0).into(),
-            Vec2::new(x_flipper * physics_size / 2.0, y_flipper * -physics_size / 2.0).into(),
-            Vec2::new(x_flipper * -physics_size / 2.0, y_flipper * physics_size / 2.0).into()
-        ]).unwrap(),
-        TileColliderType::HalfSlope1 => ColliderShape::convex_hull(&[
-            Vec2::new(x_flipper * -physics_size / 2.0, y_flipper * -physics_size / 2.0).into(),
-            Vec2::new(x_flipper * physics_size / 2.0, y_flipper * -physics_size / 2.0).into(),
-            Vec2::new(x_flipper * physics_size / 2.0, 0.0).into(),
-            Vec2::new(x_flipper * -physics_size / 2.0, y_flipper * physics_size / 2.0).into()
-        ]).unwrap(),
-        TileColliderType::HalfSlope2 => ColliderShape::convex_hull(&[
-            Vec2::new(x_flipper * -physics_size / 2.0, y_flipper * -physics_size / 2.0).into(),
-            Vec2::new(x_flipper * physics_size / 2.0, y_flipper * -physics_size / 2.0).into(),
-            Vec2::new(x_flipper * -physics_size / 2.0, 0.0).into()
-        ]).unwrap(),
-        _ => ColliderShape::cuboid(physics_size / 2.0, physics_size / 2.0),
-    }
-}
+use bevy::asset::LoadState;
+use bevy::prelude::*;
+use ldtk_rust::{LayerInstance, Level, TileInstance};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use bevy_rapier2d::prelude::*;
+
+use crate::background::spawn_background;
+use crate::colliders::{bake_colliders, collider_grid, ColliderCell};
+use crate::coords::LevelCoords;
+use crate::entities::{EntitySpawnInfo, EntitySpawnRegistry};
+use crate::fields::LevelSettings;
+use crate::ldtk::{LayerParallax, LdtkLevel, LdtkProject, TileAlpha};
+use crate::player::{Player, SavedPlayerPosition};
+use crate::scale::WorldScale;
+use crate::tilemap::{cell_chunk, spawn_tile_chunks, ChunkTile, MapTile, ParallaxLayer, TileChunks, TileMap};
+use crate::world::{LevelWorld, LoadedLevel};
+
+pub const PLAYER_GROUP: u32 = 0b0001;
+pub const SOLID_GROUP: u32 = 0b0010;
+pub const ONE_WAY_GROUP: u32 = 0b0100;
+pub const HAZARD_GROUP: u32 = 0b1000;
+
+// Where level colliders come from
+pub enum CollisionSource {
+    // Shapes derived from the tiles placed in Tiles and AutoLayer layers
+    Tiles,
+    // Shapes derived from the values of the IntGrid layer with this identifier
+    IntGrid(String),
+}
+
+pub struct LevelCollision {
+    pub source: CollisionSource,
+    pub int_grid_colliders: HashMap<i64, TileColliderType>,
+}
+
+impl Default for LevelCollision {
+    fn default() -> Self {
+        Self {
+            source: CollisionSource::Tiles,
+            int_grid_colliders: HashMap::from([
+                (1, TileColliderType::Square),
+                (2, TileColliderType::OneWay),
+                (3, TileColliderType::Hazard),
+            ]),
+        }
+    }
+}
+
+// Values of an IntGrid layer, 0 means the cell is empty
+#[derive(Component)]
+pub struct IntGrid {
+    pub identifier: String,
+    pub width: i32,
+    pub height: i32,
+    pub values: Vec<i64>,
+}
+
+impl IntGrid {
+    // Value at a cell, with (0, 0) in the top left like in LDtk
+    pub fn get(&self, x: i32, y: i32) -> Option<i64> {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+        self.values.get((y * self.width + x) as usize).copied()
+    }
+
+    // All non-empty cells as (x, y, value)
+    pub fn iter(&self) -> impl Iterator<Item = (i32, i32, i64)> + '_ {
+        let width = self.width;
+        self.values
+            .iter()
+            .enumerate()
+            .filter(|(_, &value)| value != 0)
+            .map(move |(i, &value)| (i as i32 % width, i as i32 / width, value))
+    }
+}
+
+// How the colliders of a layer were baked, kept next to the TileMap so they can
+// be rebaked when tiles are changed
+#[derive(Component)]
+pub struct TileColliders {
+    // False when the layer's colliders don't come from its tiles, see CollisionSource
+    pub from_tiles: bool,
+    pub tileset_colliders: HashMap<i64, TileCollider>,
+    // Entity collider entities are children of
+    pub parent: Entity,
+    // One entity per collision group, see bake_colliders
+    pub entities: Vec<(TileColliderType, Entity)>,
+}
+
+impl TileColliders {
+    // Collider a tile placed in a cell of this layer gets
+    pub fn tile_collider(&self, cell: IVec2, tile: &MapTile) -> Option<ColliderCell> {
+        let (collider_type, flip_x, flip_y) =
+            get_collider_type(Some(&self.tileset_colliders), tile.tile_id as i32, tile.flip_x, tile.flip_y);
+        if collider_type == TileColliderType::None {
+            return None;
+        }
+        Some(ColliderCell { x: cell.x, y: cell.y, collider_type, flip_x, flip_y })
+    }
+
+    // Bakes the colliders in the TileMap again. Groups that already have an entity
+    // get their shape replaced, so rapier keeps treating them as the same collider.
+    pub fn rebake(
+        &mut self,
+        commands: &mut Commands,
+        tile_map: &TileMap,
+        collider_shapes: &mut Query<&mut ColliderShapeComponent>,
+    ) {
+        let cells: Vec<ColliderCell> = tile_map.iter().filter_map(|(_, map_cell)| map_cell.collider.clone()).collect();
+        let physics_scale = tile_map.coords.scale.physics_scale;
+        let baked = bake_colliders(
+            &cells,
+            tile_map.width,
+            tile_map.height,
+            tile_map.coords.cell_size(),
+            tile_map.coords.origin,
+            physics_scale,
+        );
+
+        // groups that are gone now
+        let (kept, removed): (Vec<_>, Vec<_>) = self.entities
+            .drain(..)
+            .partition(|(group, _)| baked.iter().any(|(baked_group, _)| baked_group == group));
+        for (_, entity) in removed {
+            commands.entity(entity).despawn_recursive();
+        }
+        self.entities = kept;
+
+        for (group, shape) in baked {
+            let existing = self.entities.iter().find(|(existing, _)| *existing == group).map(|(_, entity)| *entity);
+            match existing {
+                Some(entity) => {
+                    if let Ok(mut collider_shape) = collider_shapes.get_mut(entity) {
+                        *collider_shape = shape.into();
+                    }
+                },
+                None => {
+                    let entity = commands
+                        .spawn_bundle(collider_bundle(&group, shape, Vec3::ZERO, physics_scale))
+                        .id();
+                    commands.entity(self.parent).push_children(&[entity]);
+                    self.entities.push((group, entity));
+                },
+            }
+        }
+    }
+}
+
+// Level waiting for its project (and the project's tilesets) to finish loading
+pub struct PendingLevel {
+    pub project: Handle<LdtkProject>,
+    pub level_id: String,
+    // Set once loading of the level's external file has been started
+    pub external_level: Option<Handle<LdtkLevel>>,
+}
+
+impl PendingLevel {
+    pub fn new(project: Handle<LdtkProject>, level_id: &str) -> Self {
+        Self {
+            project,
+            level_id: level_id.to_string(),
+            external_level: None,
+        }
+    }
+}
+
+// Level that is currently spawned
+pub struct ActiveLevel {
+    pub project: Handle<LdtkProject>,
+    pub level_id: String,
+    // The level's .ldtkl file when it's saved separately
+    pub external_level: Option<Handle<LdtkLevel>>,
+}
+
+// Everything spawned for a level is a child of its root, despawning the root
+// recursively tears the whole level down again
+#[derive(Component)]
+pub struct LevelRoot {
+    pub level_id: String,
+}
+
+// Unloads whatever level is spawned and loads another one
+pub struct LoadLevel {
+    pub project: Handle<LdtkProject>,
+    pub level_id: String,
+    // Some lays the project's levels out as a world and streams in neighbours
+    // within this many world pixels of the player, see LevelWorld. None spawns
+    // only this level, centered on the screen.
+    pub world_load_distance: Option<f32>,
+}
+
+// Despawns every spawned level
+pub struct UnloadLevel;
+
+// Why a level could not be spawned. Sent as an event so the game can react,
+// the level is skipped and everything else keeps running.
+#[derive(Debug, Clone)]
+pub enum LevelLoadError {
+    // The project file itself failed to load
+    ProjectFailed { level_id: String },
+    UnknownLevel { level_id: String },
+    BadColour { level_id: String, colour: String },
+    // A tile layer without a tileset, or with a tileset the project doesn't define
+    MissingTileset { level_id: String, layer: String, tileset_uid: Option<i64> },
+    // A level without layers whose external .ldtkl file is missing or failed to load
+    MissingExternalFile { level_id: String, path: Option<String> },
+    UnsupportedLayer { level_id: String, layer: String, layer_type: String },
+    // A layer, or the tileset it uses, with a grid size of 0 or less
+    BadGridSize { level_id: String, layer: String, tileset_uid: Option<i64>, grid_size: i64 },
+}
+
+impl fmt::Display for LevelLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelLoadError::ProjectFailed { level_id } =>
+                write!(f, "project for level '{}' failed to load", level_id),
+            LevelLoadError::UnknownLevel { level_id } =>
+                write!(f, "level with identifier '{}' does not exist in project", level_id),
+            LevelLoadError::BadColour { level_id, colour } =>
+                write!(f, "level '{}' has invalid background colour '{}'", level_id, colour),
+            LevelLoadError::MissingTileset { level_id, layer, tileset_uid: Some(uid) } =>
+                write!(f, "layer '{}' in level '{}' uses unknown tileset {}", layer, level_id, uid),
+            LevelLoadError::MissingTileset { level_id, layer, tileset_uid: None } =>
+                write!(f, "tile layer '{}' in level '{}' has no tileset", layer, level_id),
+            LevelLoadError::MissingExternalFile { level_id, path: Some(path) } =>
+                write!(f, "could not load external file '{}' for level '{}'", path, level_id),
+            LevelLoadError::MissingExternalFile { level_id, path: None } =>
+                write!(f, "level '{}' has no layers and no external file", level_id),
+            LevelLoadError::UnsupportedLayer { level_id, layer, layer_type } =>
+                write!(f, "layer '{}' in level '{}' has unsupported type '{}'", layer, level_id, layer_type),
+            LevelLoadError::BadGridSize { level_id, layer, tileset_uid: Some(uid), grid_size } =>
+                write!(f, "tileset {} of layer '{}' in level '{}' has invalid grid size {}", uid, layer, level_id, grid_size),
+            LevelLoadError::BadGridSize { level_id, layer, tileset_uid: None, grid_size } =>
+                write!(f, "layer '{}' in level '{}' has invalid grid size {}", layer, level_id, grid_size),
+        }
+    }
+}
+
+impl std::error::Error for LevelLoadError {}
+
+// Area covered by a level, in world pixels
+#[derive(Clone, Copy)]
+pub struct LevelBounds {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl LevelBounds {
+    pub fn contains(&self, point: Vec2) -> bool {
+        point.x >= self.min.x && point.x <= self.max.x && point.y >= self.min.y && point.y <= self.max.y
+    }
+
+    pub fn distance_to(&self, point: Vec2) -> f32 {
+        point.clamp(self.min, self.max).distance(point)
+    }
+}
+
+// Where a level ends up when levels are laid out by their LDtk world position
+pub fn level_world_bounds(project: &LdtkProject, level: &Level, scale: &WorldScale) -> LevelBounds {
+    let position = project.world_position(level);
+    let min = Vec2::new(position.x as f32, -(position.y as f32 + level.px_hei as f32)) * scale.tile_scale;
+    let size = Vec2::new(level.px_wid as f32, level.px_hei as f32) * scale.tile_scale;
+    LevelBounds { min, max: min + size }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_pending_level(
+    mut commands: Commands,
+    pending_level: Option<ResMut<PendingLevel>>,
+    asset_server: Res<AssetServer>,
+    projects: Res<Assets<LdtkProject>>,
+    external_levels: Res<Assets<LdtkLevel>>,
+    spawn_registry: Res<EntitySpawnRegistry>,
+    collision: Res<LevelCollision>,
+    world_scale: Res<WorldScale>,
+    world: Option<ResMut<LevelWorld>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    images: Res<Assets<Image>>,
+    mut load_errors: EventWriter<LevelLoadError>,
+) {
+    let mut pending_level = match pending_level {
+        Some(pending_level) => pending_level,
+        None => return,
+    };
+
+    let load_state = match projects.get(&pending_level.project) {
+        Some(project) => asset_server.get_group_load_state(
+            std::iter::once(pending_level.project.id)
+                .chain(project.tileset_images.values().map(|image| image.id))
+                .chain(project.backgrounds.values().map(|background| background.image.id))
+        ),
+        None => asset_server.get_load_state(&pending_level.project),
+    };
+
+    match load_state {
+        LoadState::Loaded => {
+            let pending_level = &mut *pending_level;
+            let project = projects.get(&pending_level.project).unwrap();
+            let in_world = world.is_some();
+            let level = resolve_level(
+                project,
+                &pending_level.level_id,
+                &asset_server,
+                &external_levels,
+                &mut pending_level.external_level,
+            );
+            let root = match level {
+                Ok(Some(level)) => load_level(
+                    &mut commands,
+                    &mut meshes,
+                    &mut materials,
+                    &images,
+                    &spawn_registry,
+                    &collision,
+                    &world_scale,
+                    project,
+                    level,
+                    in_world,
+                    &[],
+                ),
+                // external level file is still loading
+                Ok(None) => return,
+                Err(error) => Err(error),
+            };
+            let root = match root {
+                Ok(root) => root,
+                Err(error) => {
+                    println!("Failed to load level: {}", error);
+                    load_errors.send(error);
+                    commands.remove_resource::<PendingLevel>();
+                    return;
+                }
+            };
+            if let Some(mut world) = world {
+                if let Some(level) = find_level(project, &pending_level.level_id) {
+                    world.loaded.insert(level.identifier.clone(), LoadedLevel {
+                        bounds: level_world_bounds(project, level, &world_scale),
+                        root,
+                        external_level: pending_level.external_level.clone(),
+                    });
+                }
+            }
+            commands.insert_resource(ActiveLevel {
+                project: pending_level.project.clone(),
+                level_id: pending_level.level_id.clone(),
+                external_level: pending_level.external_level.clone(),
+            });
+            commands.remove_resource::<PendingLevel>();
+        },
+        LoadState::Failed => {
+            let error = LevelLoadError::ProjectFailed { level_id: pending_level.level_id.clone() };
+            println!("Failed to load level: {}", error);
+            load_errors.send(error);
+            commands.remove_resource::<PendingLevel>();
+        },
+        _ => {}
+    }
+}
+
+// A level with its layers, and the tile alpha ldtk_rust doesn't read
+#[derive(Clone, Copy)]
+pub struct ResolvedLevel<'a> {
+    pub level: &'a Level,
+    pub tile_alpha: Option<&'a TileAlpha>,
+}
+
+pub fn find_level<'a>(project: &'a LdtkProject, level_id: &str) -> Option<&'a Level> {
+    project.project.levels.iter().find(|level| level.identifier == level_id)
+}
+
+// Finds the layers of a level. Levels saved to separate files are loaded through
+// the asset server on first use, `external_level` keeps the handle between calls.
+// Returns Ok(None) while the file is still loading.
+pub fn resolve_level<'a>(
+    project: &'a LdtkProject,
+    level_id: &str,
+    asset_server: &AssetServer,
+    external_levels: &'a Assets<LdtkLevel>,
+    external_level: &mut Option<Handle<LdtkLevel>>,
+) -> Result<Option<ResolvedLevel<'a>>, LevelLoadError> {
+    let level = find_level(project, level_id)
+        .ok_or_else(|| LevelLoadError::UnknownLevel { level_id: level_id.to_string() })?;
+    if level.layer_instances.is_some() {
+        return Ok(Some(ResolvedLevel { level, tile_alpha: project.tile_alpha.get(level_id) }));
+    }
+
+    let path = project.external_levels
+        .get(level_id)
+        .ok_or_else(|| LevelLoadError::MissingExternalFile { level_id: level_id.to_string(), path: None })?;
+    let handle = external_level.get_or_insert_with(|| asset_server.load(&path[..]));
+
+    match asset_server.get_load_state(&*handle) {
+        LoadState::Loaded => Ok(external_levels.get(&*handle).map(|external| ResolvedLevel {
+            level: &external.level,
+            tile_alpha: Some(&external.tile_alpha),
+        })),
+        LoadState::Failed => Err(LevelLoadError::MissingExternalFile {
+            level_id: level_id.to_string(),
+            path: Some(path.clone()),
+        }),
+        _ => Ok(None),
+    }
+}
+
+// Tears down the spawned levels on LoadLevel or UnloadLevel, a LoadLevel then
+// queues up the requested level. Only the last LoadLevel of a frame counts.
+pub fn handle_level_events(
+    mut commands: Commands,
+    mut load_events: EventReader<LoadLevel>,
+    mut unload_events: EventReader<UnloadLevel>,
+    level_roots: Query<Entity, With<LevelRoot>>,
+    detached_players: Query<Entity, (With<Player>, Without<Parent>)>,
+    world: Option<ResMut<LevelWorld>>,
+) {
+    let load = load_events.iter().last();
+    let unload = unload_events.iter().count() > 0;
+    if load.is_none() && !unload {
+        return;
+    }
+
+    despawn_levels(&mut commands, &level_roots, &detached_players, world);
+    commands.insert_resource(ClearColor::default());
+    commands.remove_resource::<LevelSettings>();
+    commands.remove_resource::<LevelCoords>();
+    commands.remove_resource::<ActiveLevel>();
+    commands.remove_resource::<PendingLevel>();
+    // each LoadLevel decides again whether it's a world
+    commands.remove_resource::<LevelWorld>();
+
+    if let Some(load) = load {
+        println!("Loading level: {}", load.level_id);
+        commands.insert_resource(PendingLevel::new(load.project.clone(), &load.level_id));
+        if let Some(load_distance) = load.world_load_distance {
+            commands.insert_resource(LevelWorld::new(load_distance));
+        }
+    }
+}
+
+// Despawns every level root with everything under it. In a world the player is
+// detached from its level so it can move between levels, it goes separately.
+fn despawn_levels(
+    commands: &mut Commands,
+    level_roots: &Query<Entity, With<LevelRoot>>,
+    detached_players: &Query<Entity, (With<Player>, Without<Parent>)>,
+    world: Option<ResMut<LevelWorld>>,
+) {
+    for entity in level_roots.iter().chain(detached_players.iter()) {
+        commands.entity(entity).despawn_recursive();
+    }
+    if let Some(mut world) = world {
+        world.loaded.clear();
+        world.loading.clear();
+        world.failed.clear();
+        world.current = None;
+    }
+}
+
+// Respawns the active level when its project file (or an external level file)
+// changes on disk, or when the world scale changes
+#[allow(clippy::too_many_arguments)]
+pub fn reload_modified_level(
+    mut commands: Commands,
+    mut project_events: EventReader<AssetEvent<LdtkProject>>,
+    mut level_events: EventReader<AssetEvent<LdtkLevel>>,
+    active_level: Option<Res<ActiveLevel>>,
+    level_roots: Query<Entity, With<LevelRoot>>,
+    detached_players: Query<Entity, (With<Player>, Without<Parent>)>,
+    players: Query<&ColliderPositionComponent, With<Player>>,
+    world: Option<ResMut<LevelWorld>>,
+    world_scale: Res<WorldScale>,
+) {
+    let active_level = match active_level {
+        Some(active_level) => active_level,
+        None => return,
+    };
+
+    let project_modified = project_events.iter().any(|event| match event {
+        AssetEvent::Modified { handle } => *handle == active_level.project,
+        _ => false,
+    });
+    // only the external files of levels that are spawned
+    let level_modified = level_events.iter().any(|event| match event {
+        AssetEvent::Modified { handle } => {
+            active_level.external_level.as_ref() == Some(handle)
+                || world.as_ref().map_or(false, |world| {
+                    world.loaded.values().any(|loaded| loaded.external_level.as_ref() == Some(handle))
+                })
+        },
+        _ => false,
+    });
+    // a new scale means new tile sizes, physics positions stay the same
+    let rescaled = world_scale.is_changed() && !world_scale.is_added();
+    let modified = project_modified || level_modified || rescaled;
+
+    if modified {
+        println!("Reloading level: {}", active_level.level_id);
+
+        // the player is respawned with the level, put it back where it was
+        if let Some(position) = players.iter().next() {
+            commands.insert_resource(SavedPlayerPosition(
+                Vec2::new(position.translation.x, position.translation.y)
+            ));
+        }
+
+        despawn_levels(&mut commands, &level_roots, &detached_players, world);
+        commands.insert_resource(PendingLevel::new(active_level.project.clone(), &active_level.level_id));
+    }
+}
+
+// Spawns a level under a new LevelRoot and returns the root. With `in_world`
+// the level is placed at its LDtk world position instead of the center of the screen.
+// Entities with an identifier in `skip_entities` are not spawned.
+// The level is checked before anything is spawned, so an error leaves nothing behind.
+#[allow(clippy::too_many_arguments)]
+pub fn load_level(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    images: &Assets<Image>,
+    spawn_registry: &EntitySpawnRegistry,
+    collision: &LevelCollision,
+    scale: &WorldScale,
+    ldtk_project: &LdtkProject,
+    level: ResolvedLevel,
+    in_world: bool,
+    skip_entities: &[&str],
+) -> Result<Entity, LevelLoadError> {
+    let project = &ldtk_project.project;
+    let ResolvedLevel { level, tile_alpha } = level;
+    let (layers, bg_color) = validate_level(ldtk_project, level)?;
+
+    // load tilesets, the atlases are only used for tile UVs
+    let mut atlases = HashMap::new();
+    for tileset in project.defs.tilesets.iter() {
+        let texture_handle = match ldtk_project.tileset_images.get(&tileset.uid) {
+            Some(texture_handle) => texture_handle.clone(),
+            None => continue,
+        };
+        // check_layer keeps layers from using these
+        if tileset.tile_grid_size <= 0 {
+            continue;
+        }
+        atlases.insert(tileset.uid, TextureAtlas::from_grid(
+            texture_handle,
+            Vec2::new(tileset.tile_grid_size as f32, tileset.tile_grid_size as f32),
+            (tileset.px_wid / tileset.tile_grid_size) as usize,
+            (tileset.px_hei / tileset.tile_grid_size) as usize,
+        ));
+    }
+    // one material per tileset and alpha, shared between layers
+    let mut tile_materials = HashMap::new();
+    
+    // set bg color and settings, in a world they follow the level the player is in instead
+    if !in_world {
+        commands.insert_resource(ClearColor(bg_color));
+        commands.insert_resource(LevelSettings::from_fields(&level.identifier, &level.field_instances));
+    }
+
+    let world_position = if in_world { Some(ldtk_project.world_position(level)) } else { None };
+    let coords = LevelCoords::new(level, project.default_grid_size, world_position, *scale);
+    if !in_world {
+        commands.insert_resource(coords);
+    }
+    let level_center = coords.ldtk_px_to_world(Vec2::new(level.px_wid as f32, level.px_hei as f32) / 2.0);
+    // spawned up front so layers can parent things to it
+    let root = commands.spawn().id();
+    let mut spawned = Vec::new();
+
+    if let Some(background) = ldtk_project.backgrounds.get(&level.identifier) {
+        let level_size = Vec2::new(level.px_wid as f32, level.px_hei as f32);
+        spawned.extend(spawn_background(commands, meshes, materials, images, background, level_size, &coords));
+    }
+
+    for (z_index, layer) in layers.iter().enumerate().rev() {
+        let tileset_uid = layer.tileset_def_uid.unwrap_or(-1);
+        // validate_level made sure layers with tiles have one
+        let atlas = atlases.get(&tileset_uid);
+        let tile_colliders = matches!(collision.source, CollisionSource::Tiles);
+        let tileset_colliders = ldtk_project.tile_colliders.get(&tileset_uid);
+        let mut collider_cells = Vec::new();
+        let layer_coords = coords.for_layer(layer);
+        let mut tile_map = TileMap::new(
+            &level.identifier,
+            &layer.identifier,
+            layer.c_wid as i32,
+            layer.c_hei as i32,
+            layer_coords,
+        );
+        let layer_tiles = LayerTiles {
+            layer,
+            layer_index: z_index,
+            tile_alpha,
+            tile_scale: scale.tile_scale,
+            parallax: ldtk_project.layer_parallax.get(&layer.layer_def_uid),
+            origin: layer_coords.origin,
+            anchor: level_center,
+        };
+
+        match &layer.layer_instance_type[..] {
+            "Tiles" => {
+                println!("Spawning Tiles layer: {}", layer.identifier);
+                if tile_colliders {
+                    collider_cells.extend(layer.grid_tiles.iter().filter_map(|tile| tile_collider_cell(layer, tile, tileset_colliders)));
+                }
+            },
+            "AutoLayer" => {
+                println!("Spawning AutoLayer layer: {}", layer.identifier);
+                if tile_colliders {
+                    collider_cells.extend(layer.auto_layer_tiles.iter().filter_map(|tile| tile_collider_cell(layer, tile, tileset_colliders)));
+                }
+            },
+            "IntGrid" => {
+                println!("Spawning IntGrid layer: {}", layer.identifier);
+
+                // IntGrid layers can have auto-layer rules painting tiles on top of the values
+                if atlas.is_some() && tile_colliders {
+                    collider_cells.extend(layer.auto_layer_tiles.iter().filter_map(|tile| tile_collider_cell(layer, tile, tileset_colliders)));
+                }
+
+                let int_grid = IntGrid {
+                    identifier: layer.identifier.clone(),
+                    width: layer.c_wid as i32,
+                    height: layer.c_hei as i32,
+                    values: layer.int_grid_csv.clone(),
+                };
+
+                if matches!(&collision.source, CollisionSource::IntGrid(identifier) if *identifier == layer.identifier) {
+                    for (x, y, value) in int_grid.iter() {
+                        if let Some(collider_type) = collision.int_grid_colliders.get(&value).cloned() {
+                            collider_cells.push(ColliderCell { x, y, collider_type, flip_x: false, flip_y: false });
+                        }
+                    }
+                }
+
+                spawned.push(commands
+                    .spawn()
+                    .insert(int_grid)
+                    .id());
+            },
+            "Entities" => {
+                println!("Spawning Entities layer: {}", layer.identifier);
+                for entity in layer.entity_instances.iter() {
+                    if skip_entities.contains(&&entity.identifier[..]) {
+                        continue;
+                    }
+
+                    // entities are positioned by their pivot rather than a cell
+                    let position = layer_coords
+                        .ldtk_px_to_world(Vec2::new(entity.px[0] as f32, entity.px[1] as f32))
+                        .extend(z_index as f32);
+                    let info = EntitySpawnInfo {
+                        identifier: &entity.identifier,
+                        position,
+                        size: Vec2::new(entity.width as f32, entity.height as f32) * scale.tile_scale,
+                        pivot: Vec2::new(entity.pivot[0] as f32, entity.pivot[1] as f32),
+                        fields: &entity.field_instances,
+                        scale: *scale,
+                    };
+
+                    match spawn_registry.spawn(commands, &info) {
+                        Some(entity) => spawned.push(entity),
+                        None => println!("No spawner registered for entity: {}", entity.identifier),
+                    }
+                }
+            }
+            // validate_level rejects any other layer type
+            _ => {}
+        }
+
+        if layer.layer_instance_type == "Entities" {
+            continue;
+        }
+
+        let tile_chunks = atlas.map(|atlas| {
+            let (entities, tile_chunks) = spawn_layer_tiles(
+                commands,
+                meshes,
+                materials,
+                &mut tile_materials,
+                atlas,
+                &layer_tiles,
+                root,
+                &mut tile_map,
+            );
+            spawned.extend(entities);
+            tile_chunks
+        });
+
+        // the same colliders bake_colliders ends up with, one per cell
+        for collider in collider_grid(&collider_cells, tile_map.width, tile_map.height).into_iter().flatten() {
+            if let Some(map_cell) = tile_map.get_mut(IVec2::new(collider.x, collider.y)) {
+                map_cell.collider = Some(collider);
+            }
+        }
+
+        let mut layer_colliders = TileColliders {
+            from_tiles: tile_colliders,
+            tileset_colliders: tileset_colliders.cloned().unwrap_or_default(),
+            parent: root,
+            entities: Vec::new(),
+        };
+        if !collider_cells.is_empty() {
+            let baked = bake_colliders(
+                &collider_cells,
+                layer.c_wid as i32,
+                layer.c_hei as i32,
+                layer_coords.cell_size(),
+                layer_coords.origin,
+                scale.physics_scale,
+            );
+            for (collider_type, shape) in baked {
+                let entity = commands
+                    .spawn_bundle(collider_bundle(&collider_type, shape, Vec3::ZERO, scale.physics_scale))
+                    .id();
+                layer_colliders.entities.push((collider_type, entity));
+                spawned.push(entity);
+            }
+        }
+
+        let mut tile_map_entity = commands.spawn();
+        tile_map_entity.insert(tile_map).insert(layer_colliders);
+        if let Some(tile_chunks) = tile_chunks {
+            tile_map_entity.insert(tile_chunks);
+        }
+        spawned.push(tile_map_entity.id());
+    }
+
+    Ok(commands
+        .entity(root)
+        .insert_bundle((
+            LevelRoot { level_id: level.identifier.clone() },
+            coords,
+            Transform::identity(),
+            GlobalTransform::identity(),
+        ))
+        .push_children(&spawned)
+        .id())
+}
+
+// What spawn_layer_tiles needs to know about a layer
+struct LayerTiles<'a> {
+    layer: &'a LayerInstance,
+    layer_index: usize,
+    tile_alpha: Option<&'a TileAlpha>,
+    tile_scale: f32,
+    parallax: Option<&'a LayerParallax>,
+    // top left corner of the layer
+    origin: Vec2,
+    // center of the level, parallax layers line up with the level there
+    anchor: Vec2,
+}
+
+// Spawns the tile chunks of a layer and adds its tiles to the TileMap. Tiles are
+// grouped by their alpha (times the layer opacity) as every alpha needs its own
+// material. Hidden layers are not drawn, parallax layers get a parent that
+// follows the camera. Returns the entities to add to the level root and how the
+// chunks were drawn.
+#[allow(clippy::too_many_arguments)]
+fn spawn_layer_tiles(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    tile_materials: &mut HashMap<(i64, u8), Handle<ColorMaterial>>,
+    atlas: &TextureAtlas,
+    layer_tiles: &LayerTiles,
+    root: Entity,
+    tile_map: &mut TileMap,
+) -> (Vec<Entity>, TileChunks) {
+    let layer = layer_tiles.layer;
+    let parallax_parent = match layer_tiles.parallax {
+        Some(parallax) if parallax.factor != Vec2::ZERO && layer.visible => Some(commands
+            .spawn_bundle((
+                ParallaxLayer { parallax: *parallax, anchor: layer_tiles.anchor },
+                Transform::identity(),
+                GlobalTransform::identity(),
+            ))
+            .id()),
+        _ => None,
+    };
+    let mut tile_chunks = TileChunks {
+        atlas: atlas.clone(),
+        tile_size: layer.grid_size as f32 * layer_tiles.tile_scale,
+        origin: layer_tiles.origin,
+        z: layer_tiles.layer_index as f32,
+        visible: layer.visible,
+        opacity: layer.opacity as f32,
+        parent: parallax_parent.unwrap_or(root),
+        chunks: HashMap::new(),
+        materials: HashMap::new(),
+    };
+    if layer.grid_size <= 0 {
+        return (parallax_parent.into_iter().collect(), tile_chunks);
+    }
+
+    // Tiles layers only have grid tiles and the others only auto-layer tiles,
+    // so this is the same order TileAlpha counts in
+    let tiles: Vec<(ChunkTile, u8)> = layer.grid_tiles
+        .iter()
+        .chain(layer.auto_layer_tiles.iter())
+        .enumerate()
+        .map(|(tile_index, tile)| {
+            let tile_alpha = layer_tiles.tile_alpha.map_or(1.0, |alpha| alpha.get(layer_tiles.layer_index, tile_index));
+            let alpha = (layer.opacity as f32 * tile_alpha).clamp(0.0, 1.0);
+            (ChunkTile::from_instance(tile, layer.grid_size), (alpha * 255.0).round() as u8)
+        })
+        .collect();
+
+    let mut tiles_by_alpha: BTreeMap<u8, Vec<ChunkTile>> = BTreeMap::new();
+    if layer.visible {
+        for &(tile, alpha_key) in tiles.iter().filter(|(_, alpha_key)| *alpha_key > 0) {
+            tiles_by_alpha.entry(alpha_key).or_default().push(tile);
+        }
+    }
+
+    let tileset_uid = layer.tileset_def_uid.unwrap_or(-1);
+    let mut chunks = Vec::new();
+    for (alpha_key, tiles) in tiles_by_alpha {
+        let material = tile_materials
+            .entry((tileset_uid, alpha_key))
+            .or_insert_with(|| materials.add(ColorMaterial {
+                color: Color::rgba(1.0, 1.0, 1.0, alpha_key as f32 / 255.0),
+                texture: Some(atlas.texture.clone()),
+            }));
+        tile_chunks.materials.insert(alpha_key, material.clone());
+        let spawned_chunks = spawn_tile_chunks(
+            commands,
+            meshes,
+            atlas,
+            material,
+            &tiles,
+            tile_chunks.tile_size,
+            tile_chunks.origin,
+            tile_chunks.z,
+        );
+        for (chunk, entity) in spawned_chunks {
+            tile_chunks.chunks.insert((alpha_key, chunk), entity);
+            chunks.push(entity);
+        }
+    }
+
+    for (tile, alpha_key) in tiles {
+        if let Some(map_cell) = tile_map.get_mut(tile.cell) {
+            map_cell.tiles.push(MapTile {
+                tile_id: tile.tile_id,
+                flip_x: tile.flip_x,
+                flip_y: tile.flip_y,
+                alpha: alpha_key as f32 / 255.0,
+                entity: tile_chunks.chunks.get(&(alpha_key, cell_chunk(tile.cell))).copied(),
+            });
+        }
+    }
+
+    match parallax_parent {
+        Some(parallax_parent) => {
+            commands.entity(parallax_parent).push_children(&chunks);
+            (vec![parallax_parent], tile_chunks)
+        },
+        None => (chunks, tile_chunks),
+    }
+}
+
+// Checks everything load_level would otherwise trip over, returning the
+// level's layers and background colour
+fn validate_level<'a>(
+    ldtk_project: &LdtkProject,
+    level: &'a Level,
+) -> Result<(&'a Vec<LayerInstance>, Color), LevelLoadError> {
+    let bg_color = level_bg_color(level)?;
+
+    // levels in external files have to be resolved with resolve_level first
+    let layers = level.layer_instances
+        .as_ref()
+        .ok_or_else(|| LevelLoadError::MissingExternalFile {
+            level_id: level.identifier.clone(),
+            path: level.external_rel_path.clone(),
+        })?;
+
+    for layer in layers.iter() {
+        check_layer(ldtk_project, level, layer)?;
+    }
+
+    Ok((layers, bg_color))
+}
+
+pub fn level_bg_color(level: &Level) -> Result<Color, LevelLoadError> {
+    level.bg_color
+        .strip_prefix('#')
+        .and_then(|hex| Color::hex(hex).ok())
+        .ok_or_else(|| LevelLoadError::BadColour { level_id: level.identifier.clone(), colour: level.bg_color.clone() })
+}
+
+// Whether load_level knows how to spawn a layer
+pub fn check_layer(ldtk_project: &LdtkProject, level: &Level, layer: &LayerInstance) -> Result<(), LevelLoadError> {
+    let has_tiles = match &layer.layer_instance_type[..] {
+        "Tiles" => !layer.grid_tiles.is_empty(),
+        "AutoLayer" | "IntGrid" => !layer.auto_layer_tiles.is_empty(),
+        "Entities" => false,
+        _ => return Err(LevelLoadError::UnsupportedLayer {
+            level_id: level.identifier.clone(),
+            layer: layer.identifier.clone(),
+            layer_type: layer.layer_instance_type.clone(),
+        }),
+    };
+
+    let tileset_known = layer.tileset_def_uid.map_or(false, |uid| ldtk_project.tileset_images.contains_key(&uid));
+    if has_tiles && !tileset_known {
+        return Err(LevelLoadError::MissingTileset {
+            level_id: level.identifier.clone(),
+            layer: layer.identifier.clone(),
+            tileset_uid: layer.tileset_def_uid,
+        });
+    }
+
+    // cells and tileset atlases are found by dividing by the grid size
+    if layer.grid_size <= 0 {
+        return Err(LevelLoadError::BadGridSize {
+            level_id: level.identifier.clone(),
+            layer: layer.identifier.clone(),
+            tileset_uid: None,
+            grid_size: layer.grid_size,
+        });
+    }
+    let tileset = layer.tileset_def_uid
+        .and_then(|uid| ldtk_project.project.defs.tilesets.iter().find(|tileset| tileset.uid == uid));
+    if let Some(tileset) = tileset.filter(|tileset| has_tiles && tileset.tile_grid_size <= 0) {
+        return Err(LevelLoadError::BadGridSize {
+            level_id: level.identifier.clone(),
+            layer: layer.identifier.clone(),
+            tileset_uid: Some(tileset.uid),
+            grid_size: tileset.tile_grid_size,
+        });
+    }
+    Ok(())
+}
+
+pub fn tile_flip(tile: &TileInstance) -> (bool, bool) {
+    match tile.f {
+        1 => (true, false),
+        2 => (false, true),
+        3 => (true, true),
+        _ => (false, false),
+    }
+}
+
+fn tile_collider_cell(
+    layer: &LayerInstance,
+    tile: &TileInstance,
+    tileset_colliders: Option<&HashMap<i64, TileCollider>>,
+) -> Option<ColliderCell> {
+    let (flip_x, flip_y) = tile_flip(tile);
+    let (collider_type, flip_x, flip_y) = get_collider_type(tileset_colliders, tile.t as i32, flip_x, flip_y);
+    if collider_type == TileColliderType::None {
+        return None;
+    }
+
+    Some(ColliderCell {
+        x: tile.px[0] as i32 / layer.grid_size as i32,
+        y: tile.px[1] as i32 / layer.grid_size as i32,
+        collider_type,
+        flip_x,
+        flip_y,
+    })
+}
+
+fn collider_bundle(collider_type: &TileColliderType, shape: ColliderShape, px_pos: Vec3, physics_scale: f32) -> ColliderBundle {
+    // hazards don't block movement, they only report intersections
+    let (group, rapier_type) = match collider_type {
+        TileColliderType::OneWay => (ONE_WAY_GROUP, ColliderType::Solid),
+        TileColliderType::Hazard => (HAZARD_GROUP, ColliderType::Sensor),
+        _ => (SOLID_GROUP, ColliderType::Solid),
+    };
+
+    ColliderBundle {
+        collider_type: rapier_type.into(),
+        shape: shape.into(),
+        position: (px_pos / physics_scale).into(),
+        flags: ColliderFlags {
+            collision_groups: InteractionGroups::new(group, PLAYER_GROUP),
+            ..Default::default()
+        }.into(),
+        ..Default::default()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TileColliderType {
+    Square,
+    Slope,
+    HalfSlope1,
+    HalfSlope2,
+    OneWay,
+    Hazard,
+    None,
+    // Convex or concave outline, points go from (0, 0) in the top left of the
+    // tile to (1, 1) in the bottom right
+    Polygon(Vec<Vec2>),
+}
+
+impl TileColliderType {
+    pub fn is_slope(&self) -> bool {
+        matches!(self, TileColliderType::Slope | TileColliderType::HalfSlope1 | TileColliderType::HalfSlope2)
+    }
+
+    // Collider types as they are named in LDtk enums and custom data
+    pub fn from_name(name: &str) -> Option<TileColliderType> {
+        match name {
+            "Square" => Some(TileColliderType::Square),
+            "Slope" => Some(TileColliderType::Slope),
+            "HalfSlope1" => Some(TileColliderType::HalfSlope1),
+            "HalfSlope2" => Some(TileColliderType::HalfSlope2),
+            "OneWay" => Some(TileColliderType::OneWay),
+            "Hazard" => Some(TileColliderType::Hazard),
+            "None" => Some(TileColliderType::None),
+            _ => None,
+        }
+    }
+}
+
+// Collider of a tile in a tileset, the flags say how the shape is flipped in the tileset image
+#[derive(Clone, PartialEq)]
+pub struct TileCollider {
+    pub collider_type: TileColliderType,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+// Returns the collider type for a tile and whether it should be flipped.
+// Tiles without a collider set up are squares.
+fn get_collider_type(
+    tileset_colliders: Option<&HashMap<i64, TileCollider>>,
+    tile_id: i32,
+    flip_x: bool,
+    flip_y: bool,
+) -> (TileColliderType, bool, bool) {
+    let tile_collider = find_tile_collider(tileset_colliders, tile_id)
+        .unwrap_or(TileCollider { collider_type: TileColliderType::Square, flip_x: false, flip_y: false });
+
+    (tile_collider.collider_type, tile_collider.flip_x != flip_x, tile_collider.flip_y != flip_y)
+}
+
+// The collider set up for a tile, None when the tile falls through to the default square
+pub fn find_tile_collider(
+    tileset_colliders: Option<&HashMap<i64, TileCollider>>,
+    tile_id: i32,
+) -> Option<TileCollider> {
+    tileset_colliders.and_then(|colliders| colliders.get(&(tile_id as i64)).cloned())
+}
+
+// Identifier of the tileset the hardcoded colliders below belong to
+pub const DIRT_BRICK_TILESET: &str = "DirtBrick";
+
+// Slopes of the DirtBrick tileset, from before colliders could be set up in LDtk.
+// Only used for tilesets with that identifier, colliders set up in LDtk win.
+pub fn dirt_brick_colliders() -> HashMap<i64, TileCollider> {
+    [
+        // grass
+        (6, TileColliderType::Slope, false),
+        (7, TileColliderType::Slope, true),
+        (44, TileColliderType::HalfSlope1, false),
+        (45, TileColliderType::HalfSlope2, false),
+        (46, TileColliderType::HalfSlope2, true),
+        (47, TileColliderType::HalfSlope1, true),
+
+        // stone
+        (86, TileColliderType::Slope, false),
+        (87, TileColliderType::Slope, true),
+        (124, TileColliderType::HalfSlope1, false),
+        (125, TileColliderType::HalfSlope2, false),
+        (126, TileColliderType::HalfSlope2, true),
+        (127, TileColliderType::HalfSlope1, true),
+    ]
+    .into_iter()
+    .map(|(tile_id, collider_type, flip_x)| (tile_id, TileCollider { collider_type, flip_x, flip_y: false }))
+    .collect()
+}
+
+// `physics_size` is the size of a tile in physics metres
+pub fn build_collider_shape(
+    collider_type: &TileColliderType,
+    physics_size: f32,
+    flip_x: bool,
+    flip_y: bool,
+) -> ColliderShape {
+    let x_flipper = if flip_x { -1.0 } else { 1.0 };
+    let y_flipper = if flip_y { -1.0 } else { 1.0 };
+
+    match collider_type {
+        TileColliderType::Slope => ColliderShape::convex_hull(&[
+            Vec2::new(x_flipper * -physics_size / 2.0, y_flipper * -physics_size / 2.0).into(),
+            Vec2::new(x_flipper * physics_size / 2.0, y_flipper * -physics_size / 2.0).into(),
+            Vec2::new(x_flipper * -physics_size / 2.0, y_flipper * physics_size / 2.0).into()
+        ]).unwrap(),
+        TileColliderType::HalfSlope1 => ColliderShape::convex_hull(&[
+            Vec2::new(x_flipper * -physics_size / 2.0, y_flipper * -physics_size / 2.0).into(),
+            Vec2::new(x_flipper * physics_size / 2.0, y_flipper * -physics_size / 2.0).into(),
+            Vec2::new(x_flipper * physics_size / 2.0, 0.0).into(),
+            Vec2::new(x_flipper * -physics_size / 2.0, y_flipper * physics_size / 2.0).into()
+        ]).unwrap(),
+        TileColliderType::HalfSlope2 => ColliderShape::convex_hull(&[
+            Vec2::new(x_flipper * -physics_size / 2.0, y_flipper * -physics_size / 2.0).into(),
+            Vec2::new(x_flipper * physics_size / 2.0, y_flipper * -physics_size / 2.0).into(),
+            Vec2::new(x_flipper * -physics_size / 2.0, 0.0).into()
+        ]).unwrap(),
+        TileColliderType::Polygon(points) => {
+            let vertices: Vec<Point<Real>> = points
+                .iter()
+                .map(|point| Vec2::new(
+                    x_flipper * (point.x - 0.5) * physics_size,
+                    y_flipper * (0.5 - point.y) * physics_size,
+                ).into())
+                .collect();
+            let indices: Vec<[u32; 2]> = (0..vertices.len() as u32)
+                .map(|i| [i, (i + 1) % vertices.len() as u32])
+                .collect();
+            ColliderShape::convex_decomposition(&vertices, &indices)
+        },
+        _ => ColliderShape::cuboid(physics_size / 2.0, physics_size / 2.0),
+    }
+}
@@ -0,0 +1,13 @@
+pub mod math;
+pub mod background;
+pub mod colliders;
+pub mod coords;
+pub mod entities;
+pub mod fields;
+pub mod ldtk;
+pub mod level;
+pub mod player;
+pub mod platformer;
+pub mod scale;
+pub mod tilemap;
+pub mod world;
@@ -3,12 +3,7 @@ use bevy_inspector_egui::{WorldInspectorPlugin, RegisterInspectable};
 use bevy_rapier2d::prelude::*;
 use bevy_prototype_debug_lines::*;
 
-mod math;
-mod level;
-mod player;
-mod platformer;
-
-pub const PHYSICS_SCALE: f32 = 40.0; // 1m = 40px
+use my_bevy_game::{entities, ldtk, level, platformer, player, scale, tilemap, world};
 
 fn main() {
     App::new()
@@ -19,19 +14,50 @@ fn main() {
         .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
         .add_plugin(RapierRenderPlugin)
         .insert_resource(RapierConfiguration {
-            scale: PHYSICS_SCALE,
+            scale: scale::WorldScale::default().physics_scale,
             physics_pipeline_active: false,
             ..Default::default()
         })
+        .init_resource::<entities::EntitySpawnRegistry>()
+        .init_resource::<level::LevelCollision>()
+        .init_resource::<scale::WorldScale>()
+        .add_event::<level::LoadLevel>()
+        .add_event::<level::UnloadLevel>()
+        .add_event::<level::LevelLoadError>()
+        .add_event::<world::LevelEntered>()
+        .add_event::<world::LevelExited>()
+        .add_event::<tilemap::SetTile>()
+        .add_event::<tilemap::TileChanged>()
+        .add_event::<platformer::HazardTouched>()
+        .add_event::<platformer::DashStarted>()
+        .add_event::<platformer::DashEnded>()
+        .add_asset::<ldtk::LdtkProject>()
+        .init_asset_loader::<ldtk::LdtkLoader>()
+        .add_asset::<ldtk::LdtkLevel>()
+        .init_asset_loader::<ldtk::LdtkLevelLoader>()
         .register_inspectable::<platformer::PlatformerController>()
         .register_inspectable::<platformer::PlatformerRaycaster>()
         .register_inspectable::<platformer::PlatformerCollisionInfo>()
+        .register_inspectable::<platformer::PlatformerDash>()
         .add_startup_system(setup)
+        .add_system(scale::fit_world_scale_to_window.label("fit_world_scale"))
+        .add_system(scale::sync_physics_scale.after("fit_world_scale"))
+        .add_system(level::reload_modified_level.after("fit_world_scale"))
+        .add_system(level::handle_level_events.before("spawn_pending_level"))
+        .add_system(level::spawn_pending_level.label("spawn_pending_level"))
+        .add_system(world::stream_world_levels)
+        .add_system(tilemap::update_parallax_layers.before("cull_tile_chunks"))
+        .add_system(tilemap::cull_tile_chunks.label("cull_tile_chunks"))
+        .add_system_to_stage(CoreStage::PreUpdate, tilemap::apply_tile_edits)
+        .add_system(tilemap::refresh_query_pipeline.before("platformer_collisions"))
+        .add_system(player::restore_player_position)
         .add_system(player::get_keyboard_input)
-        .add_system(platformer::platformer_controller_update.label("platformer_pre_update"))
+        .add_system(platformer::platformer_controller_update.label("platformer_pre_update").label("platformer_controller"))
+        .add_system(platformer::platformer_dash_update.label("platformer_pre_update").after("platformer_controller"))
         .add_system(platformer::update_raycaster.label("platformer_pre_update"))
         .add_system(platformer::platformer_check_collisions.label("platformer_collisions").after("platformer_pre_update"))
         .add_system(platformer::platformer_move.label("platformer_move").after("platformer_collisions"))
+        .add_system(platformer::platformer_check_hazards.after("platformer_move"))
         .add_system(bevy::input::system::exit_on_esc_system)
         .run();
 }
@@ -39,13 +65,25 @@ fn main() {
 fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut spawn_registry: ResMut<entities::EntitySpawnRegistry>,
+    mut load_level_events: EventWriter<level::LoadLevel>,
 ) {
     // cameras
     commands.spawn_bundle(OrthographicCameraBundle::new_2d());
     commands.spawn_bundle(UiCameraBundle::default());
 
-    level::load_level(&mut commands, &asset_server, &mut texture_atlases, "assets/map.ldtk", "Level_0");
-    player::spawn_player(&mut commands, Vec2::splat(0.0));
+    spawn_registry.register("Player", player::spawn_player_from_ldtk);
+
+    // hot-reload map.ldtk while the game is running, needs bevy's filesystem_watcher feature
+    if let Err(error) = asset_server.watch_for_changes() {
+        println!("Not watching assets for changes, hot-reloading is off: {:?}", error);
+    }
+
+    load_level_events.send(level::LoadLevel {
+        project: asset_server.load("map.ldtk"),
+        level_id: "Level_0".to_string(),
+        // e.g. Some(200.0) to lay out multi-level projects as a world
+        world_load_distance: None,
+    });
 }
 
@@ -1,225 +1,612 @@
-use bevy::prelude::*;
-use bevy_inspector_egui::Inspectable;
-use bevy_rapier2d::prelude::*;
-use bevy_prototype_debug_lines::*;
-
-use crate::PHYSICS_SCALE;
-use crate::math;
-
-#[derive(Component, Default)]
-pub struct PlatformerInput {
-    pub x_movement: f32,
-    pub jumping: bool,
-}
-
-#[derive(Default, Inspectable)]
-pub struct RaycastOrigins {
-    top_left: Vec2,
-    top_right: Vec2,
-    bottom_left: Vec2,
-    bottom_right: Vec2,
-}
-
-#[derive(Component, Inspectable)]
-pub struct PlatformerRaycaster {
-    margin: f32,
-    horizontal_ray_count: u32,
-    vertical_ray_count: u32,
-    horizontal_ray_spacing: f32,
-    vertical_ray_spacing: f32,
-    origins: RaycastOrigins,
-}
-
-impl Default for PlatformerRaycaster {
-    fn default() -> Self {
-        Self {
-            margin: 0.015,
-            horizontal_ray_count: 4,
-            vertical_ray_count: 4,
-            horizontal_ray_spacing: 0.0,
-            vertical_ray_spacing: 0.0,
-            origins: Default::default(),
-        }
-    }
-}
-
-pub fn update_raycaster(
-    mut query: Query<(&mut PlatformerRaycaster, &ColliderPositionComponent, &ColliderShapeComponent)>,
-) {
-    for (mut raycaster, position, shape) in query.iter_mut() {
-        // Clamp ray count
-        raycaster.horizontal_ray_count = raycaster.horizontal_ray_count.clamp(2, u32::MAX);
-        raycaster.vertical_ray_count = raycaster.vertical_ray_count.clamp(2, u32::MAX);
-
-        // Get AABB for the collider and make it smaller by margin
-        let  bounds = shape.compute_aabb(position);
-        let maxs: Vec2 = Vec2::from(bounds.maxs) - Vec2::splat(raycaster.margin);
-        let mins: Vec2 = Vec2::from(bounds.mins) + Vec2::splat(raycaster.margin);
-
-        // Calculate ray spacing
-        raycaster.horizontal_ray_spacing = (maxs.y - mins.y) / (raycaster.horizontal_ray_count - 1) as f32;
-        raycaster.vertical_ray_spacing = (maxs.x - mins.x) / (raycaster.vertical_ray_count - 1) as f32;
-
-        // Update origins
-        raycaster.origins.bottom_left = Vec2::new(mins.x, mins.y);
-        raycaster.origins.bottom_right = Vec2::new(maxs.x, mins.y);
-        raycaster.origins.top_left = Vec2::new(mins.x, maxs.y);
-        raycaster.origins.top_right = Vec2::new(maxs.x, maxs.y);
-    }
-}
-
-#[derive(Component, Inspectable)]
-pub struct PlatformerController {
-    max_speed: f32,
-    acceleration: f32,
-    deceleration: f32,
-    gravity: f32,
-    velocity: Vec2,
-}
-
-impl Default for PlatformerController {
-    fn default() -> Self {
-        Self {
-            max_speed: 5.0,
-            acceleration: 0.1,
-            deceleration: 0.1,
-            gravity: 15.0,
-            velocity: Vec2::ZERO,
-        }
-    }
-}
-
-#[derive(Component, Default)]
-pub struct PlatformerMoveDelta(Vec2);
-
-
-pub fn platformer_controller_update(
-    time: Res<Time>,
-    mut query: Query<(&mut PlatformerController, &PlatformerInput, &mut PlatformerMoveDelta, &PlatformerCollisionInfo)>,
-) {
-    for (mut controller, input, mut delta, collision_info) in query.iter_mut() {
-        if collision_info.above || collision_info.below {
-            controller.velocity.y = 0.0;
-        }
-
-        if input.jumping && collision_info.below {
-            controller.velocity.y = 8.0;
-        }
-
-        // Apply gravity
-        controller.velocity.y -= controller.gravity * time.delta_seconds();
-
-        // Horizontal movement
-        if input.x_movement != 0.0 {
-            // speed up
-            controller.velocity.x += input.x_movement * (controller.max_speed / controller.acceleration) * time.delta_seconds();
-            controller.velocity.x = controller.velocity.x.clamp(-controller.max_speed, controller.max_speed);
-        } else {
-            // slow down
-            controller.velocity.x = math::move_towards(controller.velocity.x, 0.0, (controller.max_speed / controller.deceleration) * time.delta_seconds());
-        }
-
-        delta.0 = controller.velocity * time.delta_seconds();
-    }
-}
-
-#[derive(Component, Default, Inspectable)]
-pub struct PlatformerCollisionInfo {
-    above: bool,
-    below: bool,
-    left: bool,
-    right: bool,
-}
-
-impl PlatformerCollisionInfo {
-    fn reset(&mut self) {
-        self.above = false;
-        self.below = false;
-        self.left = false;
-        self.right = false;
-    }
-}
-
-pub fn platformer_check_collisions(
-    //time: Res<Time>,
-    query_pipeline: Res<QueryPipeline>,
-    collider_query: QueryPipelineColliderComponentsQuery,
-    mut query: Query<(&mut PlatformerMoveDelta, &mut PlatformerCollisionInfo, &PlatformerRaycaster)>,
-    mut debug_lines: ResMut<DebugLines>,
-) {
-    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
-
-    for (mut delta, mut collision_info, raycaster) in query.iter_mut() {
-        collision_info.reset();
-        
-        // Horizontal collisions
-        if delta.0.x != 0.0
-        {
-            let direction_x = delta.0.x.signum();
-            let mut ray_length = delta.0.x.abs() + raycaster.margin;
-            let first_origin = if direction_x == -1.0 { raycaster.origins.bottom_left } else { raycaster.origins.bottom_right };
-
-            for i in 0..raycaster.vertical_ray_count {
-                let ray_origin = first_origin + Vec2::new(0.0, 1.0) * (raycaster.horizontal_ray_spacing * i as f32);
-                let ray = Ray::new(ray_origin.into(), (Vec2::new(1.0, 0.0) * direction_x).into());
-                let hit = query_pipeline.cast_ray(
-                    &collider_set,
-                    &ray,
-                    ray_length,
-                    true,
-                    InteractionGroups::new(0b0001, 0b0010),
-                    None
-                );
-
-                if let Some((_, toi)) = hit {
-                    delta.0.x = (toi - raycaster.margin) * direction_x;
-                    ray_length = toi;
-
-                    collision_info.left = direction_x == -1.0;
-                    collision_info.right = direction_x == 1.0;
-                }
-
-                debug_lines.line((ray_origin * PHYSICS_SCALE, 5.0).into(), ((ray_origin + Vec2::new(ray_length * direction_x, 0.0)) * PHYSICS_SCALE, 5.0).into(), 0.0);
-            }
-        }
-
-        // Vertical collisions
-        if delta.0.y != 0.0
-        {
-            let direction_y = delta.0.y.signum();
-            let mut ray_length = delta.0.y.abs() + raycaster.margin;
-            let first_origin = if direction_y == -1.0 { raycaster.origins.bottom_left } else { raycaster.origins.top_left };
-
-            for i in 0..raycaster.vertical_ray_count {
-                let ray_origin = first_origin + Vec2::new(1.0, 0.0) * (raycaster.vertical_ray_spacing * i as f32 + delta.0.x);
-                let ray = Ray::new(ray_origin.into(), (Vec2::new(0.0, 1.0) * direction_y).into());
-                let hit = query_pipeline.cast_ray(
-                    &collider_set,
-                    &ray,
-                    ray_length,
-                    true,
-                    InteractionGroups::new(0b0001, 0b0010),
-                    None
-                );
-
-                if let Some((_, toi)) = hit {
-                    delta.0.y = (toi - raycaster.margin) * direction_y;
-                    ray_length = toi;
-
-                    collision_info.below = direction_y == -1.0;
-                    collision_info.above = direction_y == 1.0;
-                }
-
-                debug_lines.line((ray_origin * PHYSICS_SCALE, 0.0).into(), ((ray_origin + Vec2::new(0.0, ray_length * direction_y)) * PHYSICS_SCALE, 0.0).into(), 0.0);
-            }
-        }
-    }
-}
-
-pub fn platformer_move(
-    mut query: Query<(&PlatformerMoveDelta, &mut ColliderPositionComponent)>,
-) {
-    for (delta, mut position) in query.iter_mut() {
-        position.translation = Vec2::new(position.translation.x + delta.0.x, position.translation.y + delta.0.y).into();
-    }
-}
+use bevy::prelude::*;
+use bevy_inspector_egui::Inspectable;
+use bevy_rapier2d::prelude::*;
+use bevy_prototype_debug_lines::*;
+
+use crate::fields::LevelSettings;
+use crate::level::{HAZARD_GROUP, ONE_WAY_GROUP, PLAYER_GROUP, SOLID_GROUP};
+use crate::math;
+use crate::scale::WorldScale;
+
+#[derive(Component, Default)]
+pub struct PlatformerInput {
+    pub x_movement: f32,
+    pub y_movement: f32,
+    // Jump button is held
+    pub jumping: bool,
+    // Jump button went down this frame
+    pub jump_pressed: bool,
+    // Jump button went up this frame
+    pub jump_released: bool,
+    // Dash button went down this frame
+    pub dash_pressed: bool,
+}
+
+#[derive(Default, Inspectable)]
+pub struct RaycastOrigins {
+    top_left: Vec2,
+    top_right: Vec2,
+    bottom_left: Vec2,
+    bottom_right: Vec2,
+}
+
+#[derive(Component, Inspectable)]
+pub struct PlatformerRaycaster {
+    margin: f32,
+    horizontal_ray_count: u32,
+    vertical_ray_count: u32,
+    horizontal_ray_spacing: f32,
+    vertical_ray_spacing: f32,
+    // Steepest slope (in degrees) that can be walked up and down, anything steeper is a wall
+    max_slope_angle: f32,
+    origins: RaycastOrigins,
+}
+
+impl Default for PlatformerRaycaster {
+    fn default() -> Self {
+        Self {
+            margin: 0.015,
+            horizontal_ray_count: 4,
+            vertical_ray_count: 4,
+            horizontal_ray_spacing: 0.0,
+            vertical_ray_spacing: 0.0,
+            max_slope_angle: 60.0,
+            origins: Default::default(),
+        }
+    }
+}
+
+pub fn update_raycaster(
+    mut query: Query<(&mut PlatformerRaycaster, &ColliderPositionComponent, &ColliderShapeComponent)>,
+) {
+    for (mut raycaster, position, shape) in query.iter_mut() {
+        // Clamp ray count
+        raycaster.horizontal_ray_count = raycaster.horizontal_ray_count.clamp(2, u32::MAX);
+        raycaster.vertical_ray_count = raycaster.vertical_ray_count.clamp(2, u32::MAX);
+
+        // Get AABB for the collider and make it smaller by margin
+        let  bounds = shape.compute_aabb(position);
+        let maxs: Vec2 = Vec2::from(bounds.maxs) - Vec2::splat(raycaster.margin);
+        let mins: Vec2 = Vec2::from(bounds.mins) + Vec2::splat(raycaster.margin);
+
+        // Calculate ray spacing
+        raycaster.horizontal_ray_spacing = (maxs.y - mins.y) / (raycaster.horizontal_ray_count - 1) as f32;
+        raycaster.vertical_ray_spacing = (maxs.x - mins.x) / (raycaster.vertical_ray_count - 1) as f32;
+
+        // Update origins
+        raycaster.origins.bottom_left = Vec2::new(mins.x, mins.y);
+        raycaster.origins.bottom_right = Vec2::new(maxs.x, mins.y);
+        raycaster.origins.top_left = Vec2::new(mins.x, maxs.y);
+        raycaster.origins.top_right = Vec2::new(maxs.x, maxs.y);
+    }
+}
+
+#[derive(Component, Inspectable)]
+pub struct PlatformerController {
+    max_speed: f32,
+    acceleration: f32,
+    deceleration: f32,
+    // Jump heights in metres, the jump is cut to the minimum when the button is let go early
+    max_jump_height: f32,
+    min_jump_height: f32,
+    // Seconds it takes to reach the highest point of a full jump, gravity follows from this
+    time_to_jump_apex: f32,
+    // Gravity is multiplied by this while falling, above 1 makes jumps feel snappier
+    fall_gravity_multiplier: f32,
+    // Seconds after walking off a ledge during which jumping still works
+    coyote_time: f32,
+    // Seconds a jump pressed in the air is remembered, so it happens on landing
+    jump_buffer_time: f32,
+    // Fastest fall while sliding down a wall
+    wall_slide_speed_max: f32,
+    // Seconds a wall slide holds on after no longer pushing into the wall, so there's time to wall jump
+    wall_stick_time: f32,
+    // Wall jump velocities, x points away from the wall. Climb is used when pushing
+    // into the wall, off without input and leap when pushing away from it.
+    wall_jump_climb: Vec2,
+    wall_jump_off: Vec2,
+    wall_leap: Vec2,
+    velocity: Vec2,
+    // Time left to jump without ground below
+    coyote_timer: f32,
+    // Time left for the last jump press to turn into a jump
+    jump_buffer_timer: f32,
+    wall_sliding: bool,
+    // Time left before letting go of the wall
+    wall_unstick_timer: f32,
+}
+
+impl Default for PlatformerController {
+    fn default() -> Self {
+        Self {
+            max_speed: 5.0,
+            acceleration: 0.1,
+            deceleration: 0.1,
+            max_jump_height: 2.0,
+            min_jump_height: 0.5,
+            time_to_jump_apex: 0.5,
+            fall_gravity_multiplier: 1.0,
+            coyote_time: 0.1,
+            jump_buffer_time: 0.1,
+            wall_slide_speed_max: 2.0,
+            wall_stick_time: 0.25,
+            wall_jump_climb: Vec2::new(3.0, 8.0),
+            wall_jump_off: Vec2::new(4.0, 4.0),
+            wall_leap: Vec2::new(7.0, 7.0),
+            velocity: Vec2::ZERO,
+            coyote_timer: 0.0,
+            jump_buffer_timer: 0.0,
+            wall_sliding: false,
+            wall_unstick_timer: 0.0,
+        }
+    }
+}
+
+impl PlatformerController {
+    // From h = g * t^2 / 2
+    pub fn gravity(&self) -> f32 {
+        2.0 * self.max_jump_height / self.time_to_jump_apex.powi(2).max(f32::EPSILON)
+    }
+
+    // Initial velocity of a full jump, from v^2 = 2 * g * h
+    pub fn max_jump_velocity(&self) -> f32 {
+        (2.0 * self.gravity() * self.max_jump_height).max(0.0).sqrt()
+    }
+
+    pub fn min_jump_velocity(&self) -> f32 {
+        (2.0 * self.gravity() * self.min_jump_height.min(self.max_jump_height)).max(0.0).sqrt()
+    }
+}
+
+#[derive(Component, Default)]
+pub struct PlatformerMoveDelta(Vec2);
+
+// Lets a platformer dash in the input direction (or the way it's facing without input)
+#[derive(Component, Inspectable)]
+pub struct PlatformerDash {
+    speed: f32,
+    // Seconds a dash lasts, gravity doesn't pull while dashing
+    duration: f32,
+    // Seconds after a dash ends before the next one can start
+    cooldown: f32,
+    // Dashes that can be done before landing again
+    air_charges: u32,
+    // Time left of the current dash, 0 when not dashing
+    dash_timer: f32,
+    cooldown_timer: f32,
+    charges: u32,
+    direction: Vec2,
+}
+
+impl Default for PlatformerDash {
+    fn default() -> Self {
+        Self {
+            speed: 20.0,
+            duration: 0.15,
+            cooldown: 0.3,
+            air_charges: 1,
+            dash_timer: 0.0,
+            cooldown_timer: 0.0,
+            charges: 1,
+            direction: Vec2::ZERO,
+        }
+    }
+}
+
+impl PlatformerDash {
+    pub fn is_dashing(&self) -> bool {
+        self.dash_timer > 0.0
+    }
+}
+
+pub struct DashStarted {
+    pub entity: Entity,
+    pub direction: Vec2,
+}
+
+pub struct DashEnded {
+    pub entity: Entity,
+}
+
+// Runs after platformer_controller_update and replaces its move delta while dashing,
+// collisions still stop the dash at walls
+pub fn platformer_dash_update(
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut PlatformerDash, &mut PlatformerController, &PlatformerInput, &mut PlatformerMoveDelta, &PlatformerCollisionInfo)>,
+    mut started_events: EventWriter<DashStarted>,
+    mut ended_events: EventWriter<DashEnded>,
+) {
+    for (entity, mut dash, mut controller, input, mut delta, collision_info) in query.iter_mut() {
+        // landing gives the air dashes back
+        if collision_info.below && !dash.is_dashing() {
+            dash.charges = dash.air_charges;
+        }
+        dash.cooldown_timer = (dash.cooldown_timer - time.delta_seconds()).max(0.0);
+
+        if input.dash_pressed && !dash.is_dashing() && dash.cooldown_timer <= 0.0 && dash.charges > 0 {
+            // 8 directions, straight ahead without input
+            let mut direction = Vec2::new(math::sign(input.x_movement), math::sign(input.y_movement));
+            if direction == Vec2::ZERO {
+                direction.x = if collision_info.face_direction < 0.0 { -1.0 } else { 1.0 };
+            }
+            dash.direction = direction.normalize();
+            dash.dash_timer = dash.duration;
+            dash.charges -= 1;
+            started_events.send(DashStarted { entity, direction: dash.direction });
+        }
+
+        if !dash.is_dashing() {
+            continue;
+        }
+
+        // overwriting the velocity keeps gravity from building up during the dash
+        controller.velocity = dash.direction * dash.speed;
+        delta.0 = controller.velocity * time.delta_seconds();
+
+        dash.dash_timer -= time.delta_seconds();
+        if dash.dash_timer <= 0.0 {
+            dash.dash_timer = 0.0;
+            dash.cooldown_timer = dash.cooldown;
+            // come out of the dash at running speed
+            controller.velocity = dash.direction * controller.max_speed;
+            ended_events.send(DashEnded { entity });
+        }
+    }
+}
+
+pub fn platformer_controller_update(
+    time: Res<Time>,
+    level_settings: Option<Res<LevelSettings>>,
+    mut query: Query<(&mut PlatformerController, &PlatformerInput, &mut PlatformerMoveDelta, &PlatformerCollisionInfo)>,
+) {
+    // levels can override gravity with a "gravity" float field
+    let gravity_override = level_settings.and_then(|settings| settings.get_float("gravity"));
+
+    for (mut controller, input, mut delta, collision_info) in query.iter_mut() {
+        // with a gravity override jumps keep their speed, so they go higher or lower
+        let gravity = gravity_override.unwrap_or_else(|| controller.gravity());
+        let max_jump_velocity = controller.max_jump_velocity();
+        let min_jump_velocity = controller.min_jump_velocity();
+
+        if collision_info.above || collision_info.below {
+            controller.velocity.y = 0.0;
+        }
+
+        // standing on the ground keeps the coyote timer full, it runs out once we're in the air
+        if collision_info.below {
+            controller.coyote_timer = controller.coyote_time;
+        } else {
+            controller.coyote_timer = (controller.coyote_timer - time.delta_seconds()).max(0.0);
+        }
+        if input.jump_pressed {
+            controller.jump_buffer_timer = controller.jump_buffer_time;
+        } else {
+            controller.jump_buffer_timer = (controller.jump_buffer_timer - time.delta_seconds()).max(0.0);
+        }
+
+        // Horizontal movement
+        if input.x_movement != 0.0 {
+            // speed up, moving faster than max speed (after a wall leap) slows down to it instead
+            let target = input.x_movement * controller.max_speed;
+            controller.velocity.x = math::move_towards(controller.velocity.x, target, (controller.max_speed / controller.acceleration) * time.delta_seconds());
+        } else {
+            // slow down
+            controller.velocity.x = math::move_towards(controller.velocity.x, 0.0, (controller.max_speed / controller.deceleration) * time.delta_seconds());
+        }
+
+        // Apply gravity
+        let gravity = if controller.velocity.y < 0.0 { gravity * controller.fall_gravity_multiplier } else { gravity };
+        controller.velocity.y -= gravity * time.delta_seconds();
+
+        // Wall sliding, falling while pushing into a wall in the air
+        let wall_direction = if collision_info.left { -1.0 } else { 1.0 };
+        let touching_wall = (collision_info.left || collision_info.right) && !collision_info.below && controller.velocity.y < 0.0;
+        let pushing_into_wall = input.x_movement != 0.0 && input.x_movement.signum() == wall_direction;
+        if touching_wall && pushing_into_wall {
+            controller.wall_sliding = true;
+            controller.wall_unstick_timer = controller.wall_stick_time;
+        } else if touching_wall && controller.wall_sliding && controller.wall_unstick_timer > 0.0 {
+            // stay on the wall for a bit after letting go, so there's time to wall jump
+            controller.wall_unstick_timer -= time.delta_seconds();
+            controller.velocity.x = 0.0;
+        } else {
+            controller.wall_sliding = false;
+        }
+        if controller.wall_sliding {
+            controller.velocity.y = controller.velocity.y.max(-controller.wall_slide_speed_max);
+        }
+
+        if controller.jump_buffer_timer > 0.0 && controller.wall_sliding {
+            let wall_jump = if input.x_movement == 0.0 {
+                controller.wall_jump_off
+            } else if input.x_movement.signum() == wall_direction {
+                controller.wall_jump_climb
+            } else {
+                controller.wall_leap
+            };
+            controller.velocity = Vec2::new(-wall_direction * wall_jump.x, wall_jump.y);
+            controller.jump_buffer_timer = 0.0;
+            controller.wall_sliding = false;
+        } else if controller.jump_buffer_timer > 0.0 && controller.coyote_timer > 0.0 {
+            // a buffered jump whose button is already up again is a short one
+            controller.velocity.y = if input.jumping { max_jump_velocity } else { min_jump_velocity };
+            // one jump per press and per ground contact
+            controller.jump_buffer_timer = 0.0;
+            controller.coyote_timer = 0.0;
+        }
+
+        // letting go of jump early cuts the jump short
+        if input.jump_released && controller.velocity.y > min_jump_velocity {
+            controller.velocity.y = min_jump_velocity;
+        }
+
+        delta.0 = controller.velocity * time.delta_seconds();
+    }
+}
+
+#[derive(Component, Default, Inspectable)]
+pub struct PlatformerCollisionInfo {
+    above: bool,
+    below: bool,
+    left: bool,
+    right: bool,
+    climbing_slope: bool,
+    descending_slope: bool,
+    // Angle of the slope under the player in degrees, 0 on flat ground
+    slope_angle: f32,
+    slope_angle_old: f32,
+    // Move delta before it was changed by collisions
+    delta_old: Vec2,
+    // Direction we last moved in horizontally, 1 right and -1 left
+    face_direction: f32,
+}
+
+impl PlatformerCollisionInfo {
+    fn reset(&mut self) {
+        self.above = false;
+        self.below = false;
+        self.left = false;
+        self.right = false;
+        self.climbing_slope = false;
+        self.descending_slope = false;
+        self.slope_angle_old = self.slope_angle;
+        self.slope_angle = 0.0;
+    }
+}
+
+pub fn platformer_check_collisions(
+    //time: Res<Time>,
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    mut query: Query<(&mut PlatformerMoveDelta, &mut PlatformerCollisionInfo, &PlatformerRaycaster)>,
+    mut debug_lines: ResMut<DebugLines>,
+    world_scale: Res<WorldScale>,
+) {
+    let physics_scale = world_scale.physics_scale;
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+
+    // distance to whatever the ray hits first and the normal of the surface there
+    let cast_ray = |origin: Vec2, direction: Vec2, length: f32, filter: u32| {
+        let ray = Ray::new(origin.into(), direction.into());
+        query_pipeline
+            .cast_ray_and_get_normal(
+                &collider_set,
+                &ray,
+                length,
+                true,
+                InteractionGroups::new(PLAYER_GROUP, filter),
+                None
+            )
+            .map(|(_, intersection)| (intersection.toi, Vec2::new(intersection.normal.x, intersection.normal.y)))
+    };
+
+    for (mut delta, mut collision_info, raycaster) in query.iter_mut() {
+        collision_info.reset();
+        collision_info.delta_old = delta.0;
+
+        if delta.0.x != 0.0 {
+            collision_info.face_direction = delta.0.x.signum();
+        }
+
+        if delta.0.y < 0.0 {
+            descend_slope(&mut delta.0, &mut collision_info, raycaster, &cast_ray);
+        }
+
+        // Horizontal collisions, also checked standing still so we know when we're against a wall
+        {
+            let moving = delta.0.x != 0.0;
+            let direction_x = if collision_info.face_direction < 0.0 { -1.0 } else { 1.0 };
+            let mut ray_length = (delta.0.x.abs() + raycaster.margin).max(2.0 * raycaster.margin);
+            let first_origin = if direction_x == -1.0 { raycaster.origins.bottom_left } else { raycaster.origins.bottom_right };
+
+            for i in 0..raycaster.vertical_ray_count {
+                let ray_origin = first_origin + Vec2::new(0.0, 1.0) * (raycaster.horizontal_ray_spacing * i as f32);
+                let hit = cast_ray(ray_origin, Vec2::new(1.0, 0.0) * direction_x, ray_length, SOLID_GROUP);
+
+                // the lowest ray can start inside the slope we were on last frame, that's not a wall.
+                // Anything else starting inside a collider still is one.
+                let on_slope = i == 0 && collision_info.slope_angle_old != 0.0;
+                let hit = hit.filter(|&(toi, _)| toi > 0.0 || !on_slope);
+                // standing still next to a slope isn't touching a wall
+                let hit = hit.filter(|&(_, normal)| moving || normal.angle_between(Vec2::Y).abs().to_degrees() > raycaster.max_slope_angle);
+
+                if let Some((toi, normal)) = hit {
+                    let slope_angle = normal.angle_between(Vec2::Y).abs().to_degrees();
+
+                    // only the lowest ray decides whether we walk up a slope
+                    if i == 0 && slope_angle <= raycaster.max_slope_angle {
+                        if collision_info.descending_slope {
+                            collision_info.descending_slope = false;
+                            delta.0 = collision_info.delta_old;
+                        }
+                        // walk up to the foot of a new slope first, then climb with what's left
+                        let mut distance_to_slope = 0.0;
+                        if slope_angle != collision_info.slope_angle_old {
+                            distance_to_slope = toi - raycaster.margin;
+                            delta.0.x -= distance_to_slope * direction_x;
+                        }
+                        climb_slope(&mut delta.0, slope_angle, &mut collision_info);
+                        delta.0.x += distance_to_slope * direction_x;
+                    }
+
+                    if !collision_info.climbing_slope || slope_angle > raycaster.max_slope_angle {
+                        delta.0.x = (toi - raycaster.margin) * direction_x;
+                        ray_length = toi;
+
+                        // a wall on a slope also stops us from going further up
+                        if collision_info.climbing_slope {
+                            delta.0.y = collision_info.slope_angle.to_radians().tan() * delta.0.x.abs();
+                        }
+
+                        collision_info.left = direction_x == -1.0;
+                        collision_info.right = direction_x == 1.0;
+                    }
+                }
+
+                debug_lines.line((ray_origin * physics_scale, 5.0).into(), ((ray_origin + Vec2::new(ray_length * direction_x, 0.0)) * physics_scale, 5.0).into(), 0.0);
+            }
+        }
+
+        // Vertical collisions
+        if delta.0.y != 0.0
+        {
+            let direction_y = delta.0.y.signum();
+            let mut ray_length = delta.0.y.abs() + raycaster.margin;
+            let first_origin = if direction_y == -1.0 { raycaster.origins.bottom_left } else { raycaster.origins.top_left };
+
+            for i in 0..raycaster.vertical_ray_count {
+                let ray_origin = first_origin + Vec2::new(1.0, 0.0) * (raycaster.vertical_ray_spacing * i as f32 + delta.0.x);
+                let hit = cast_ray(ray_origin, Vec2::new(0.0, 1.0) * direction_y, ray_length, SOLID_GROUP);
+
+                // one-way platforms only block from above, a ray starting inside one means we are passing through it
+                let one_way_hit = if direction_y == -1.0 {
+                    cast_ray(ray_origin, Vec2::new(0.0, -1.0), ray_length, ONE_WAY_GROUP).filter(|&(toi, _)| toi > 0.0)
+                } else {
+                    None
+                };
+                let hit = match (hit, one_way_hit) {
+                    (Some(hit), Some(one_way_hit)) => Some(if one_way_hit.0 < hit.0 { one_way_hit } else { hit }),
+                    (hit, one_way_hit) => hit.or(one_way_hit),
+                };
+
+                if let Some((toi, _)) = hit {
+                    delta.0.y = (toi - raycaster.margin) * direction_y;
+                    ray_length = toi;
+
+                    // hitting a ceiling while climbing also cuts the horizontal movement
+                    if collision_info.climbing_slope {
+                        delta.0.x = delta.0.y / collision_info.slope_angle.to_radians().tan() * delta.0.x.signum();
+                    }
+
+                    collision_info.below = direction_y == -1.0;
+                    collision_info.above = direction_y == 1.0;
+                }
+
+                debug_lines.line((ray_origin * physics_scale, 0.0).into(), ((ray_origin + Vec2::new(0.0, ray_length * direction_y)) * physics_scale, 0.0).into(), 0.0);
+            }
+
+            // a steeper or flatter slope may start within this frame's movement
+            if collision_info.climbing_slope {
+                let direction_x = delta.0.x.signum();
+                let ray_length = delta.0.x.abs() + raycaster.margin;
+                let first_origin = if direction_x == -1.0 { raycaster.origins.bottom_left } else { raycaster.origins.bottom_right };
+                let ray_origin = first_origin + Vec2::new(0.0, delta.0.y);
+
+                if let Some((toi, normal)) = cast_ray(ray_origin, Vec2::new(1.0, 0.0) * direction_x, ray_length, SOLID_GROUP) {
+                    let slope_angle = normal.angle_between(Vec2::Y).abs().to_degrees();
+                    if slope_angle != collision_info.slope_angle {
+                        delta.0.x = (toi - raycaster.margin) * direction_x;
+                        collision_info.slope_angle = slope_angle;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Turns horizontal movement into movement along a slope, unless we're moving up faster than that already (jumping)
+fn climb_slope(delta: &mut Vec2, slope_angle: f32, collision_info: &mut PlatformerCollisionInfo) {
+    let move_distance = delta.x.abs();
+    let climb_y = slope_angle.to_radians().sin() * move_distance;
+
+    if delta.y <= climb_y {
+        delta.y = climb_y;
+        delta.x = slope_angle.to_radians().cos() * move_distance * delta.x.signum();
+        collision_info.below = true;
+        collision_info.climbing_slope = true;
+        collision_info.slope_angle = slope_angle;
+    }
+}
+
+// Keeps us on a slope we're walking down, instead of walking off it into the air and falling back down every frame
+fn descend_slope(
+    delta: &mut Vec2,
+    collision_info: &mut PlatformerCollisionInfo,
+    raycaster: &PlatformerRaycaster,
+    cast_ray: &impl Fn(Vec2, Vec2, f32, u32) -> Option<(f32, Vec2)>,
+) {
+    if delta.x == 0.0 {
+        return;
+    }
+
+    // the back corner is the one above the slope
+    let direction_x = delta.x.signum();
+    let ray_origin = if direction_x == -1.0 { raycaster.origins.bottom_right } else { raycaster.origins.bottom_left };
+    let (toi, normal) = match cast_ray(ray_origin, Vec2::new(0.0, -1.0), f32::MAX, SOLID_GROUP | ONE_WAY_GROUP) {
+        Some(hit) => hit,
+        None => return,
+    };
+
+    let slope_angle = normal.angle_between(Vec2::Y).abs().to_degrees();
+    let slopes_down = normal.x.signum() == direction_x;
+    let close_enough = toi - raycaster.margin <= slope_angle.to_radians().tan() * delta.x.abs();
+    if slope_angle != 0.0 && slope_angle <= raycaster.max_slope_angle && slopes_down && close_enough {
+        let move_distance = delta.x.abs();
+        delta.x = slope_angle.to_radians().cos() * move_distance * direction_x;
+        delta.y -= slope_angle.to_radians().sin() * move_distance;
+
+        collision_info.slope_angle = slope_angle;
+        collision_info.descending_slope = true;
+        collision_info.below = true;
+    }
+}
+
+// Sent every frame a platformer overlaps a hazard collider
+pub struct HazardTouched {
+    pub entity: Entity,
+    pub hazard: Entity,
+}
+
+// Hazards are sensors the raycasts don't see, their colliders are checked for overlaps instead
+pub fn platformer_check_hazards(
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    query: Query<(Entity, &ColliderPositionComponent, &ColliderShapeComponent), With<PlatformerRaycaster>>,
+    mut hazard_events: EventWriter<HazardTouched>,
+) {
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+
+    for (entity, position, shape) in query.iter() {
+        let position: &Isometry<Real> = position;
+        let shape: &ColliderShape = shape;
+        query_pipeline.intersections_with_shape(
+            &collider_set,
+            position,
+            &**shape,
+            InteractionGroups::new(PLAYER_GROUP, HAZARD_GROUP),
+            None,
+            |handle| {
+                hazard_events.send(HazardTouched { entity, hazard: handle.entity() });
+                true
+            },
+        );
+    }
+}
+
+pub fn platformer_move(
+    mut query: Query<(&PlatformerMoveDelta, &mut ColliderPositionComponent)>,
+) {
+    for (delta, mut position) in query.iter_mut() {
+        position.translation = Vec2::new(position.translation.x + delta.0.x, position.translation.y + delta.0.y).into();
+    }
+}
@@ -1,53 +1,94 @@
-use bevy::prelude::*;
-use bevy_rapier2d::prelude::*;
-
-use crate::PHYSICS_SCALE;
-use crate::platformer::*;
-
-pub fn spawn_player(commands: &mut Commands, position: Vec2) {  
-    const PLAYER_HEIGHT: f32 = 1.5;
-    const PLAYER_WIDTH: f32 = 1.0;
-    commands
-        .spawn_bundle(ColliderBundle {
-            shape: ColliderShape::cuboid(PLAYER_WIDTH / 2.0, PLAYER_HEIGHT / 2.0).into(),
-            position: position.into(),
-            flags: ColliderFlags {
-                collision_groups: InteractionGroups::new(0b0000, 0b0000),
-                ..Default::default()
-            }.into(),
-            ..Default::default()
-        })
-        .insert(ColliderPositionSync::Discrete)
-        //.insert(ColliderDebugRender::with_id(1))
-        .insert_bundle(SpriteBundle {
-            transform: Transform::from_xyz(0.0, 0.0, 1.0),
-            sprite: Sprite {
-                color: Color::rgb(0.5, 0.5, 0.5),
-                custom_size: Some(Vec2::new(PLAYER_WIDTH * PHYSICS_SCALE, PLAYER_HEIGHT * PHYSICS_SCALE)),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(PlatformerRaycaster::default())
-        .insert(PlatformerMoveDelta::default())
-        .insert(PlatformerController::default())
-        .insert(PlatformerCollisionInfo::default())
-        .insert(PlatformerInput::default());
-}
-
-pub fn get_keyboard_input(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut player_inputs: Query<&mut PlatformerInput>,
-) {
-    for mut player_input in player_inputs.iter_mut() {
-        player_input.x_movement = 0.0;
-        if keyboard_input.pressed(KeyCode::A) {
-            player_input.x_movement -= 1.0;
-        }
-        if keyboard_input.pressed(KeyCode::D) {
-            player_input.x_movement += 1.0;
-        }
-
-        player_input.jumping = keyboard_input.pressed(KeyCode::Space) || keyboard_input.pressed(KeyCode::W);
-    }
-}
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::entities::EntitySpawnInfo;
+use crate::platformer::*;
+use crate::scale::WorldScale;
+
+#[derive(Component)]
+pub struct Player;
+
+// Player position to restore when the level is respawned (e.g. hot-reload)
+pub struct SavedPlayerPosition(pub Vec2);
+
+pub fn spawn_player_from_ldtk(commands: &mut Commands, info: &EntitySpawnInfo) -> Entity {
+    spawn_player(commands, info.center() / info.scale.physics_scale, &info.scale)
+}
+
+pub fn spawn_player(commands: &mut Commands, position: Vec2, scale: &WorldScale) -> Entity {  
+    const PLAYER_HEIGHT: f32 = 1.5;
+    const PLAYER_WIDTH: f32 = 1.0;
+    commands
+        .spawn_bundle(ColliderBundle {
+            shape: ColliderShape::cuboid(PLAYER_WIDTH / 2.0, PLAYER_HEIGHT / 2.0).into(),
+            position: position.into(),
+            flags: ColliderFlags {
+                collision_groups: InteractionGroups::new(0b0000, 0b0000),
+                ..Default::default()
+            }.into(),
+            ..Default::default()
+        })
+        .insert(ColliderPositionSync::Discrete)
+        //.insert(ColliderDebugRender::with_id(1))
+        .insert_bundle(SpriteBundle {
+            transform: Transform::from_xyz(0.0, 0.0, 1.0),
+            sprite: Sprite {
+                color: Color::rgb(0.5, 0.5, 0.5),
+                custom_size: Some(Vec2::new(PLAYER_WIDTH, PLAYER_HEIGHT) * scale.physics_scale),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(PlatformerRaycaster::default())
+        .insert(PlatformerMoveDelta::default())
+        .insert(PlatformerController::default())
+        .insert(PlatformerCollisionInfo::default())
+        .insert(PlatformerDash::default())
+        .insert(PlatformerInput::default())
+        .insert(Player)
+        .id()
+}
+
+pub fn restore_player_position(
+    mut commands: Commands,
+    saved_position: Option<Res<SavedPlayerPosition>>,
+    mut players: Query<&mut ColliderPositionComponent, Added<Player>>,
+) {
+    let saved_position = match saved_position {
+        Some(saved_position) => saved_position,
+        None => return,
+    };
+
+    for mut position in players.iter_mut() {
+        position.translation = saved_position.0.into();
+        commands.remove_resource::<SavedPlayerPosition>();
+    }
+}
+
+pub fn get_keyboard_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut player_inputs: Query<&mut PlatformerInput>,
+) {
+    for mut player_input in player_inputs.iter_mut() {
+        player_input.x_movement = 0.0;
+        if keyboard_input.pressed(KeyCode::A) {
+            player_input.x_movement -= 1.0;
+        }
+        if keyboard_input.pressed(KeyCode::D) {
+            player_input.x_movement += 1.0;
+        }
+        player_input.y_movement = 0.0;
+        if keyboard_input.pressed(KeyCode::S) {
+            player_input.y_movement -= 1.0;
+        }
+        if keyboard_input.pressed(KeyCode::W) {
+            player_input.y_movement += 1.0;
+        }
+
+        // W is up for dashing, so only space jumps
+        player_input.jumping = keyboard_input.pressed(KeyCode::Space);
+        player_input.jump_pressed = keyboard_input.just_pressed(KeyCode::Space);
+        player_input.jump_released = keyboard_input.just_released(KeyCode::Space);
+        player_input.dash_pressed = keyboard_input.just_pressed(KeyCode::LShift);
+    }
+}
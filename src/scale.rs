@@ -0,0 +1,82 @@
+use bevy::prelude::*;
+use bevy::window::WindowResized;
+use bevy_rapier2d::prelude::*;
+
+// How big the world is on screen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorldScale {
+    // Screen pixels per LDtk pixel
+    pub tile_scale: f32,
+    // Screen pixels per physics metre
+    pub physics_scale: f32,
+}
+
+impl Default for WorldScale {
+    fn default() -> Self {
+        // 1m = 16 LDtk pixels = 40 screen pixels
+        Self {
+            tile_scale: 2.5,
+            physics_scale: 40.0,
+        }
+    }
+}
+
+impl WorldScale {
+    // Largest whole number of screen pixels per LDtk pixel that still fits
+    // `view_height` LDtk pixels in the window, so pixel art stays crisp
+    pub fn fit_window(window_height: f32, view_height: f32, ldtk_pixels_per_metre: f32) -> Self {
+        let tile_scale = (window_height / view_height).floor().max(1.0);
+        Self {
+            tile_scale,
+            physics_scale: tile_scale * ldtk_pixels_per_metre,
+        }
+    }
+}
+
+// With this resource the WorldScale follows the window size, see WorldScale::fit_window.
+// Without it the WorldScale stays at whatever it was set to. To scale tiles by
+// whole pixels so 288 LDtk pixels fit the window height:
+//
+//     commands.insert_resource(AutoWorldScale { view_height: 288.0, ldtk_pixels_per_metre: 16.0 });
+pub struct AutoWorldScale {
+    // How many LDtk pixels should fit vertically
+    pub view_height: f32,
+    pub ldtk_pixels_per_metre: f32,
+}
+
+pub fn fit_world_scale_to_window(
+    auto_scale: Option<Res<AutoWorldScale>>,
+    windows: Res<Windows>,
+    mut resized_events: EventReader<WindowResized>,
+    mut world_scale: ResMut<WorldScale>,
+) {
+    let auto_scale = match auto_scale {
+        Some(auto_scale) => auto_scale,
+        None => return,
+    };
+    // is_changed is also true right after the resource is inserted
+    let resized = resized_events.iter().count() > 0;
+    if !resized && !auto_scale.is_changed() {
+        return;
+    }
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+
+    let scale = WorldScale::fit_window(window.height(), auto_scale.view_height, auto_scale.ldtk_pixels_per_metre);
+    if *world_scale != scale {
+        println!("World scale: {} px per tile pixel, {} px per metre", scale.tile_scale, scale.physics_scale);
+        *world_scale = scale;
+    }
+}
+
+// Rapier draws and syncs colliders with its own copy of the physics scale
+pub fn sync_physics_scale(
+    world_scale: Res<WorldScale>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    if world_scale.is_changed() {
+        rapier_config.scale = world_scale.physics_scale;
+    }
+}
@@ -0,0 +1,771 @@
+use bevy::prelude::*;
+use bevy::render::camera::{CameraPlugin, OrthographicProjection};
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::sprite::{MaterialMesh2dBundle, Mesh2dHandle};
+use bevy_rapier2d::prelude::*;
+use ldtk_rust::TileInstance;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::colliders::ColliderCell;
+use crate::coords::LevelCoords;
+use crate::ldtk::LayerParallax;
+use crate::level::{tile_flip, TileColliderType, TileColliders};
+
+// Chunks are CHUNK_SIZE x CHUNK_SIZE grid cells
+pub const CHUNK_SIZE: i32 = 32;
+
+// Tiles of one layer in one chunk, drawn as a single mesh. The chunk's transform
+// is its top left corner, `size` (in world pixels) is used to hide chunks that
+// are outside of the camera.
+#[derive(Component)]
+pub struct TileChunk {
+    pub size: Vec2,
+}
+
+// Parent of the chunks of a parallax layer, moved relative to the camera.
+// `anchor` is the point where the layer lines up with the level (the level center).
+#[derive(Component)]
+pub struct ParallaxLayer {
+    pub parallax: LayerParallax,
+    pub anchor: Vec2,
+}
+
+// Grid cell of a tile, (0, 0) is the top left cell of the layer
+pub fn tile_cell(tile: &TileInstance, grid_size: i64) -> IVec2 {
+    IVec2::new(tile.px[0].div_euclid(grid_size) as i32, tile.px[1].div_euclid(grid_size) as i32)
+}
+
+// Chunk a grid cell is drawn in
+pub fn cell_chunk(cell: IVec2) -> IVec2 {
+    IVec2::new(cell.x.div_euclid(CHUNK_SIZE), cell.y.div_euclid(CHUNK_SIZE))
+}
+
+// A tile as it's drawn in a chunk
+#[derive(Clone, Copy)]
+pub struct ChunkTile {
+    pub cell: IVec2,
+    pub tile_id: i64,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+impl ChunkTile {
+    pub fn from_instance(tile: &TileInstance, grid_size: i64) -> Self {
+        let (flip_x, flip_y) = tile_flip(tile);
+        Self {
+            cell: tile_cell(tile, grid_size),
+            tile_id: tile.t,
+            flip_x,
+            flip_y,
+        }
+    }
+}
+
+// Groups the tiles of a layer into chunks and spawns one mesh per chunk, using
+// the atlas for UVs. `tile_size` is the size of a cell in world pixels, `origin`
+// the world position of the top left corner of the layer. Returns the chunks
+// that were spawned with their chunk coordinates.
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_tile_chunks(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    atlas: &TextureAtlas,
+    material: &Handle<ColorMaterial>,
+    tiles: &[ChunkTile],
+    tile_size: f32,
+    origin: Vec2,
+    z: f32,
+) -> Vec<(IVec2, Entity)> {
+    // sorted so chunks are always spawned in the same order
+    let mut chunks: BTreeMap<(i32, i32), Vec<ChunkTile>> = BTreeMap::new();
+    for tile in tiles.iter() {
+        let chunk = cell_chunk(tile.cell);
+        chunks.entry((chunk.x, chunk.y)).or_default().push(*tile);
+    }
+
+    chunks
+        .into_iter()
+        .map(|((chunk_x, chunk_y), chunk_tiles)| {
+            let chunk = IVec2::new(chunk_x, chunk_y);
+            let entity = spawn_chunk(commands, meshes, atlas, material, &chunk_tiles, chunk, tile_size, origin, z);
+            (chunk, entity)
+        })
+        .collect()
+}
+
+// Spawns the mesh of a single chunk, the tiles have to be in the chunk
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_chunk(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    atlas: &TextureAtlas,
+    material: &Handle<ColorMaterial>,
+    tiles: &[ChunkTile],
+    chunk: IVec2,
+    tile_size: f32,
+    origin: Vec2,
+    z: f32,
+) -> Entity {
+    let chunk_size = CHUNK_SIZE as f32 * tile_size;
+    // top left corner of the chunk, vertices are relative to it
+    let chunk_origin = origin + Vec2::new(chunk.x as f32, -chunk.y as f32) * chunk_size;
+    let mesh = build_chunk_mesh(atlas, tiles, chunk, tile_size);
+
+    commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            mesh: Mesh2dHandle(meshes.add(mesh)),
+            material: material.clone(),
+            transform: Transform::from_translation(chunk_origin.extend(z)),
+            ..Default::default()
+        })
+        .insert(TileChunk { size: Vec2::splat(chunk_size) })
+        .id()
+}
+
+// One quad per tile, relative to the top left corner of the chunk
+fn build_chunk_mesh(
+    atlas: &TextureAtlas,
+    tiles: &[ChunkTile],
+    chunk: IVec2,
+    tile_size: f32,
+) -> Mesh {
+    let mut positions = Vec::with_capacity(tiles.len() * 4);
+    let mut normals = Vec::with_capacity(tiles.len() * 4);
+    let mut uvs = Vec::with_capacity(tiles.len() * 4);
+    let mut indices = Vec::with_capacity(tiles.len() * 6);
+
+    for tile in tiles.iter() {
+        let rect = match atlas.textures.get(tile.tile_id as usize) {
+            Some(rect) => rect,
+            None => continue,
+        };
+
+        let cell = tile.cell - chunk * CHUNK_SIZE;
+        let top_left = Vec2::new(cell.x as f32, -cell.y as f32) * tile_size;
+        let (left, right) = (top_left.x, top_left.x + tile_size);
+        let (top, bottom) = (top_left.y, top_left.y - tile_size);
+
+        let (mut uv_left, mut uv_right) = (rect.min.x / atlas.size.x, rect.max.x / atlas.size.x);
+        let (mut uv_top, mut uv_bottom) = (rect.min.y / atlas.size.y, rect.max.y / atlas.size.y);
+        if tile.flip_x {
+            std::mem::swap(&mut uv_left, &mut uv_right);
+        }
+        if tile.flip_y {
+            std::mem::swap(&mut uv_top, &mut uv_bottom);
+        }
+
+        let first = positions.len() as u32;
+        positions.extend([[left, top, 0.0], [right, top, 0.0], [right, bottom, 0.0], [left, bottom, 0.0]]);
+        normals.extend([[0.0, 0.0, 1.0]; 4]);
+        uvs.extend([[uv_left, uv_top], [uv_right, uv_top], [uv_right, uv_bottom], [uv_left, uv_bottom]]);
+        indices.extend([first, first + 2, first + 1, first, first + 3, first + 2]);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+fn camera_2d<'a>(
+    cameras: &'a Query<(&Camera, &OrthographicProjection, &GlobalTransform)>,
+) -> Option<(&'a OrthographicProjection, &'a GlobalTransform)> {
+    cameras
+        .iter()
+        .find(|(camera, _, _)| camera.name.as_deref() == Some(CameraPlugin::CAMERA_2D))
+        .map(|(_, projection, transform)| (projection, transform))
+}
+
+// Positions parallax layers: a factor of 0 keeps the layer in place, 1 moves it
+// along with the camera. With scaling, far away layers are also drawn smaller.
+pub fn update_parallax_layers(
+    cameras: Query<(&Camera, &OrthographicProjection, &GlobalTransform)>,
+    mut layers: Query<(&ParallaxLayer, &mut Transform)>,
+) {
+    let camera_position = match camera_2d(&cameras) {
+        Some((_, transform)) => transform.translation.truncate(),
+        None => return,
+    };
+
+    for (layer, mut transform) in layers.iter_mut() {
+        let parallax = &layer.parallax;
+        let scale = if parallax.scaling {
+            (Vec2::ONE - parallax.factor).max(Vec2::splat(0.01))
+        } else {
+            Vec2::ONE
+        };
+        // scaled around the anchor instead of the origin
+        let position = layer.anchor * (Vec2::ONE - scale) + (camera_position - layer.anchor) * parallax.factor;
+
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+        transform.scale = scale.extend(1.0);
+    }
+}
+
+// Hides chunks that don't overlap the 2d camera's view
+pub fn cull_tile_chunks(
+    cameras: Query<(&Camera, &OrthographicProjection, &GlobalTransform)>,
+    mut chunks: Query<(&TileChunk, &GlobalTransform, &mut Visibility)>,
+) {
+    let (projection, camera_transform) = match camera_2d(&cameras) {
+        Some(camera) => camera,
+        None => return,
+    };
+
+    let camera_position = camera_transform.translation.truncate();
+    let view_min = camera_position + Vec2::new(projection.left, projection.bottom) * projection.scale;
+    let view_max = camera_position + Vec2::new(projection.right, projection.top) * projection.scale;
+
+    for (chunk, transform, mut visibility) in chunks.iter_mut() {
+        // chunks grow right and down from their top left corner
+        let size = chunk.size * transform.scale.truncate();
+        let top_left = transform.translation.truncate();
+        let min = top_left - Vec2::new(0.0, size.y);
+        let max = top_left + Vec2::new(size.x, 0.0);
+
+        let visible = min.x <= view_max.x
+            && max.x >= view_min.x
+            && min.y <= view_max.y
+            && max.y >= view_min.y;
+        if visibility.is_visible != visible {
+            visibility.is_visible = visible;
+        }
+    }
+}
+
+// A tile drawn in a grid cell
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapTile {
+    pub tile_id: i64,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    // Tile alpha times the layer opacity
+    pub alpha: f32,
+    // Chunk the tile is drawn in, None when the layer is hidden or the tile fully transparent
+    pub entity: Option<Entity>,
+}
+
+// Everything in one grid cell of a layer
+#[derive(Clone, Debug, Default)]
+pub struct MapCell {
+    // Bottom to top, LDtk can stack several tiles in one cell
+    pub tiles: Vec<MapTile>,
+    // Collider the cell got baked with, None when the player can pass through
+    pub collider: Option<ColliderCell>,
+}
+
+impl MapCell {
+    // The tile that's drawn on top
+    pub fn tile(&self) -> Option<&MapTile> {
+        self.tiles.last()
+    }
+
+    pub fn collider_type(&self) -> TileColliderType {
+        self.collider
+            .as_ref()
+            .map_or(TileColliderType::None, |collider| collider.collider_type.clone())
+    }
+
+    // Chunk the top tile is drawn in
+    pub fn entity(&self) -> Option<Entity> {
+        self.tile().and_then(|tile| tile.entity)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty() && self.collider.is_none()
+    }
+}
+
+// Where a raycast over a TileMap stopped
+#[derive(Clone, Copy, Debug)]
+pub struct GridHit {
+    pub cell: IVec2,
+    // World position where the ray entered the cell
+    pub point: Vec2,
+    // Side of the cell the ray came through, zero when it started inside the cell
+    pub normal: Vec2,
+    // World pixels from the start of the ray
+    pub distance: f32,
+}
+
+// The tiles and colliders of a spawned layer by grid cell, so gameplay code can
+// ask what is at a position. Spawned for every layer that isn't an Entities
+// layer, as a child of the LevelRoot. Cells are (0, 0) in the top left like in LDtk.
+#[derive(Component)]
+pub struct TileMap {
+    pub level_id: String,
+    pub identifier: String,
+    pub width: i32,
+    pub height: i32,
+    // Coordinates of the layer, parallax is not taken into account
+    pub coords: LevelCoords,
+    cells: Vec<MapCell>,
+}
+
+impl TileMap {
+    pub fn new(level_id: &str, identifier: &str, width: i32, height: i32, coords: LevelCoords) -> Self {
+        Self {
+            level_id: level_id.to_string(),
+            identifier: identifier.to_string(),
+            width,
+            height,
+            coords,
+            cells: vec![MapCell::default(); (width.max(0) * height.max(0)) as usize],
+        }
+    }
+
+    pub fn contains(&self, cell: IVec2) -> bool {
+        cell.x >= 0 && cell.y >= 0 && cell.x < self.width && cell.y < self.height
+    }
+
+    pub fn get(&self, cell: IVec2) -> Option<&MapCell> {
+        if !self.contains(cell) {
+            return None;
+        }
+        self.cells.get((cell.y * self.width + cell.x) as usize)
+    }
+
+    pub fn get_mut(&mut self, cell: IVec2) -> Option<&mut MapCell> {
+        if !self.contains(cell) {
+            return None;
+        }
+        self.cells.get_mut((cell.y * self.width + cell.x) as usize)
+    }
+
+    // Cell under a world position, None outside of the layer
+    pub fn at_world(&self, world: Vec2) -> Option<(IVec2, &MapCell)> {
+        let cell = self.coords.world_to_grid(world);
+        self.get(cell).map(|map_cell| (cell, map_cell))
+    }
+
+    // Id of the top tile in a cell
+    pub fn tile_id(&self, cell: IVec2) -> Option<i64> {
+        self.get(cell)?.tile().map(|tile| tile.tile_id)
+    }
+
+    // TileColliderType::None outside of the layer
+    pub fn collider_type(&self, cell: IVec2) -> TileColliderType {
+        self.get(cell).map_or(TileColliderType::None, |map_cell| map_cell.collider_type())
+    }
+
+    // Cells that have a tile or a collider
+    pub fn iter(&self) -> impl Iterator<Item = (IVec2, &MapCell)> + '_ {
+        let width = self.width;
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, map_cell)| !map_cell.is_empty())
+            .map(move |(i, map_cell)| (IVec2::new(i as i32 % width, i as i32 / width), map_cell))
+    }
+
+    // Cells next to a cell that are inside the layer, with or without the diagonal ones
+    pub fn neighbours(&self, cell: IVec2, diagonal: bool) -> impl Iterator<Item = (IVec2, &MapCell)> + '_ {
+        const OFFSETS: [(i32, i32); 8] = [(0, -1), (1, 0), (0, 1), (-1, 0), (1, -1), (1, 1), (-1, 1), (-1, -1)];
+        let count = if diagonal { 8 } else { 4 };
+        OFFSETS[..count]
+            .iter()
+            .map(move |&(x, y)| cell + IVec2::new(x, y))
+            .filter_map(move |neighbour| self.get(neighbour).map(|map_cell| (neighbour, map_cell)))
+    }
+
+    // Walks the cells along a ray one by one (Amanatides & Woo) and returns the
+    // first cell `hit` accepts, e.g. `|cell| cell.collider.is_some()`. `origin`
+    // and `max_distance` are in world pixels.
+    pub fn raycast(
+        &self,
+        origin: Vec2,
+        direction: Vec2,
+        max_distance: f32,
+        mut hit: impl FnMut(&MapCell) -> bool,
+    ) -> Option<GridHit> {
+        let direction = direction.normalize_or_zero();
+        if direction == Vec2::ZERO {
+            return None;
+        }
+
+        // grid space, y points down
+        let start = self.coords.world_to_ldtk_px(origin) / self.coords.grid_size;
+        let grid_direction = Vec2::new(direction.x, -direction.y);
+        let step = IVec2::new(step_sign(grid_direction.x), step_sign(grid_direction.y));
+        let cell_size = self.coords.cell_size();
+
+        // world distance between cell borders, and to the next border, along each axis
+        let border_distance = |start: f32, direction: f32, step: i32| {
+            if step == 0 {
+                return (f32::INFINITY, f32::INFINITY);
+            }
+            let delta = cell_size / direction.abs();
+            let to_border = if step > 0 { start.floor() + 1.0 - start } else { start - start.floor() };
+            (delta, to_border * delta)
+        };
+        let (delta_x, mut next_x) = border_distance(start.x, grid_direction.x, step.x);
+        let (delta_y, mut next_y) = border_distance(start.y, grid_direction.y, step.y);
+
+        let mut cell = start.floor().as_ivec2();
+        let mut distance = 0.0;
+        let mut normal = Vec2::ZERO;
+        loop {
+            if let Some(map_cell) = self.get(cell) {
+                if hit(map_cell) {
+                    return Some(GridHit { cell, point: origin + direction * distance, normal, distance });
+                }
+            }
+
+            // once outside and moving away the ray can't come back
+            let leaving_x = (cell.x < 0 && step.x <= 0) || (cell.x >= self.width && step.x >= 0);
+            let leaving_y = (cell.y < 0 && step.y <= 0) || (cell.y >= self.height && step.y >= 0);
+            if leaving_x || leaving_y {
+                return None;
+            }
+
+            if next_x < next_y {
+                distance = next_x;
+                next_x += delta_x;
+                cell.x += step.x;
+                normal = Vec2::new(-step.x as f32, 0.0);
+            } else {
+                distance = next_y;
+                next_y += delta_y;
+                cell.y += step.y;
+                // grid y is flipped, moving down enters through the top
+                normal = Vec2::new(0.0, step.y as f32);
+            }
+            if distance > max_distance {
+                return None;
+            }
+        }
+    }
+}
+
+fn step_sign(value: f32) -> i32 {
+    if value > 0.0 {
+        1
+    } else if value < 0.0 {
+        -1
+    } else {
+        0
+    }
+}
+
+// How the chunks of a layer are drawn, kept next to the TileMap so chunks can be
+// redrawn when tiles are changed. Only layers with a tileset have one.
+#[derive(Component)]
+pub struct TileChunks {
+    pub atlas: TextureAtlas,
+    // Size of a cell in world pixels
+    pub tile_size: f32,
+    // World position of the top left corner of the layer
+    pub origin: Vec2,
+    pub z: f32,
+    pub visible: bool,
+    // Alpha of tiles placed at runtime
+    pub opacity: f32,
+    // Entity chunks are children of, the level root or the layer's parallax parent
+    pub parent: Entity,
+    // Chunk entities by alpha (0 to 255) and chunk coordinates
+    pub chunks: HashMap<(u8, IVec2), Entity>,
+    pub materials: HashMap<u8, Handle<ColorMaterial>>,
+}
+
+impl TileChunks {
+    // Respawns the meshes of a chunk from the tiles in the TileMap and points
+    // the tiles at their new chunk entities
+    pub fn redraw_chunk(
+        &mut self,
+        commands: &mut Commands,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<ColorMaterial>,
+        tile_map: &mut TileMap,
+        chunk: IVec2,
+    ) {
+        let old_chunks: Vec<(u8, IVec2)> = self.chunks.keys().filter(|(_, c)| *c == chunk).copied().collect();
+        for key in old_chunks {
+            if let Some(entity) = self.chunks.remove(&key) {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        if !self.visible {
+            return;
+        }
+
+        let first_cell = chunk * CHUNK_SIZE;
+        let cells = (0..CHUNK_SIZE).flat_map(|y| (0..CHUNK_SIZE).map(move |x| first_cell + IVec2::new(x, y)));
+
+        let mut tiles_by_alpha: BTreeMap<u8, Vec<ChunkTile>> = BTreeMap::new();
+        for cell in cells.clone() {
+            for tile in tile_map.get(cell).into_iter().flat_map(|map_cell| map_cell.tiles.iter()) {
+                let alpha_key = (tile.alpha * 255.0).round() as u8;
+                if alpha_key > 0 {
+                    tiles_by_alpha.entry(alpha_key).or_default().push(ChunkTile {
+                        cell,
+                        tile_id: tile.tile_id,
+                        flip_x: tile.flip_x,
+                        flip_y: tile.flip_y,
+                    });
+                }
+            }
+        }
+
+        for (alpha_key, tiles) in tiles_by_alpha {
+            let texture = self.atlas.texture.clone();
+            let material = self.materials
+                .entry(alpha_key)
+                .or_insert_with(|| materials.add(ColorMaterial {
+                    color: Color::rgba(1.0, 1.0, 1.0, alpha_key as f32 / 255.0),
+                    texture: Some(texture),
+                }));
+            let entity = spawn_chunk(commands, meshes, &self.atlas, material, &tiles, chunk, self.tile_size, self.origin, self.z);
+            commands.entity(self.parent).push_children(&[entity]);
+            self.chunks.insert((alpha_key, chunk), entity);
+        }
+
+        for cell in cells {
+            if let Some(map_cell) = tile_map.get_mut(cell) {
+                for tile in map_cell.tiles.iter_mut() {
+                    let alpha_key = (tile.alpha * 255.0).round() as u8;
+                    tile.entity = self.chunks.get(&(alpha_key, chunk)).copied();
+                }
+            }
+        }
+    }
+}
+
+// Puts a tile into a cell of a layer, or clears the cell. The chunk is redrawn
+// and, when the layer's colliders come from its tiles, the colliders are rebaked.
+pub struct SetTile {
+    // Entity with the layer's TileMap
+    pub tile_map: Entity,
+    pub cell: IVec2,
+    pub tile_id: Option<i64>,
+    // How the tile is flipped, like the f bits of LDtk tiles
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+// Sent for every SetTile that was applied
+pub struct TileChanged {
+    pub tile_map: Entity,
+    pub cell: IVec2,
+    pub tile_id: Option<i64>,
+}
+
+// Applies SetTile events. Runs before the Update stage, so the chunks and
+// colliders it spawns exist by the time the platformer looks for collisions.
+pub fn apply_tile_edits(
+    mut commands: Commands,
+    mut set_tile_events: EventReader<SetTile>,
+    mut tile_changed_events: EventWriter<TileChanged>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut layers: Query<(&mut TileMap, Option<&mut TileChunks>, Option<&mut TileColliders>)>,
+    mut collider_shapes: Query<&mut ColliderShapeComponent>,
+) {
+    // several edits to a layer in one frame are redrawn and rebaked once
+    let mut edited: HashMap<Entity, (HashSet<IVec2>, bool)> = HashMap::new();
+
+    for event in set_tile_events.iter() {
+        let (mut tile_map, chunks, colliders) = match layers.get_mut(event.tile_map) {
+            Ok(layer) => layer,
+            Err(_) => {
+                println!("Can't set tile, {:?} has no TileMap", event.tile_map);
+                continue;
+            }
+        };
+        let opacity = chunks.as_ref().map_or(1.0, |chunks| chunks.opacity);
+        let map_cell = match tile_map.get_mut(event.cell) {
+            Some(map_cell) => map_cell,
+            None => {
+                println!("Can't set tile, cell {} is outside of the layer", event.cell);
+                continue;
+            }
+        };
+
+        map_cell.tiles = event.tile_id
+            .map(|tile_id| MapTile { tile_id, flip_x: event.flip_x, flip_y: event.flip_y, alpha: opacity, entity: None })
+            .into_iter()
+            .collect();
+
+        let (chunks_to_redraw, rebake) = edited.entry(event.tile_map).or_default();
+        chunks_to_redraw.insert(cell_chunk(event.cell));
+        if let Some(colliders) = colliders.filter(|colliders| colliders.from_tiles) {
+            map_cell.collider = map_cell.tile().and_then(|tile| colliders.tile_collider(event.cell, tile));
+            *rebake = true;
+        }
+
+        tile_changed_events.send(TileChanged {
+            tile_map: event.tile_map,
+            cell: event.cell,
+            tile_id: event.tile_id,
+        });
+    }
+
+    for (entity, (chunks_to_redraw, rebake)) in edited {
+        let (mut tile_map, chunks, colliders) = match layers.get_mut(entity) {
+            Ok(layer) => layer,
+            Err(_) => continue,
+        };
+        if let Some(mut chunks) = chunks {
+            for chunk in chunks_to_redraw {
+                chunks.redraw_chunk(&mut commands, &mut meshes, &mut materials, &mut tile_map, chunk);
+            }
+        }
+        if let Some(mut colliders) = colliders.filter(|_| rebake) {
+            colliders.rebake(&mut commands, &tile_map, &mut collider_shapes);
+        }
+    }
+}
+
+// Rebuilds rapier's query pipeline whenever colliders were added or reshaped since
+// this last ran, or tiles were edited in PreUpdate. Rapier only updates the pipeline
+// after the Update stage, and from then on incrementally, so this makes the
+// platformer's raycasts see the colliders that exist now: edited tiles in the
+// same frame, and freshly spawned levels without relying on how the incremental
+// update treats colliders without a body. Removed colliders are taken out by
+// bevy_rapier's own systems after the Update stage they were despawned in.
+pub fn refresh_query_pipeline(
+    mut tile_changed_events: EventReader<TileChanged>,
+    changed_colliders: Query<(), Changed<ColliderShapeComponent>>,
+    mut query_pipeline: ResMut<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+) {
+    let tiles_changed = tile_changed_events.iter().count() > 0;
+    let colliders_changed = changed_colliders.iter().next().is_some();
+    if !tiles_changed && !colliders_changed {
+        return;
+    }
+
+    // A new pipeline builds its tree from every collider's current position, bodies
+    // and islands aren't looked at for that, so empty ones are enough
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+    *query_pipeline = QueryPipeline::new();
+    query_pipeline.update_with_mode(
+        &IslandManager::new(),
+        &RigidBodySet::new(),
+        &collider_set,
+        QueryPipelineMode::CurrentPosition,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scale::WorldScale;
+
+    // 5x3 cells of 16 world pixels, the top left corner at (0, 0) so cell (x, y)
+    // spans x * 16..x * 16 + 16 and -y * 16 - 16..-y * 16 in the world
+    fn map(solid: &[IVec2]) -> TileMap {
+        let scale = WorldScale { tile_scale: 1.0, physics_scale: 16.0 };
+        let mut tile_map = TileMap::new("Level", "Collisions", 5, 3, LevelCoords::in_world(Vec2::ZERO, 16.0, scale));
+        for &cell in solid {
+            tile_map.get_mut(cell).unwrap().collider = Some(ColliderCell {
+                x: cell.x,
+                y: cell.y,
+                collider_type: TileColliderType::Square,
+                flip_x: false,
+                flip_y: false,
+            });
+        }
+        tile_map
+    }
+
+    fn solid(map_cell: &MapCell) -> bool {
+        map_cell.collider.is_some()
+    }
+
+    fn assert_close(a: Vec2, b: Vec2) {
+        assert!(a.abs_diff_eq(b, 1e-3), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn axis_aligned_rays() {
+        // right, enters through the left side
+        let hit = map(&[IVec2::new(3, 1)]).raycast(Vec2::new(8.0, -24.0), Vec2::X, 100.0, solid).unwrap();
+        assert_eq!(hit.cell, IVec2::new(3, 1));
+        assert_close(hit.point, Vec2::new(48.0, -24.0));
+        assert_eq!(hit.normal, Vec2::new(-1.0, 0.0));
+        assert!((hit.distance - 40.0).abs() < 1e-3);
+
+        // left
+        let hit = map(&[IVec2::new(0, 1)]).raycast(Vec2::new(72.0, -24.0), -Vec2::X, 100.0, solid).unwrap();
+        assert_eq!(hit.cell, IVec2::new(0, 1));
+        assert_eq!(hit.normal, Vec2::new(1.0, 0.0));
+        assert!((hit.distance - 56.0).abs() < 1e-3);
+
+        // down in the world is up the grid rows, enters through the top
+        let hit = map(&[IVec2::new(2, 2)]).raycast(Vec2::new(40.0, -8.0), -Vec2::Y, 100.0, solid).unwrap();
+        assert_eq!(hit.cell, IVec2::new(2, 2));
+        assert_close(hit.point, Vec2::new(40.0, -32.0));
+        assert_eq!(hit.normal, Vec2::new(0.0, 1.0));
+
+        // up, enters through the bottom
+        let hit = map(&[IVec2::new(2, 0)]).raycast(Vec2::new(40.0, -40.0), Vec2::Y, 100.0, solid).unwrap();
+        assert_eq!(hit.cell, IVec2::new(2, 0));
+        assert_close(hit.point, Vec2::new(40.0, -16.0));
+        assert_eq!(hit.normal, Vec2::new(0.0, -1.0));
+    }
+
+    #[test]
+    fn diagonal_ray_visits_cells_in_order() {
+        let tile_map = map(&[IVec2::new(3, 1)]);
+        let mut visited = Vec::new();
+        let hit = tile_map
+            .raycast(Vec2::new(8.0, -8.0), Vec2::new(2.0, -1.0), 100.0, |map_cell| {
+                visited.push(map_cell.collider.is_some());
+                map_cell.collider.is_some()
+            })
+            .unwrap();
+        assert_eq!(hit.cell, IVec2::new(3, 1));
+        assert_eq!(hit.normal, Vec2::new(-1.0, 0.0));
+        assert_close(hit.point, Vec2::new(48.0, -28.0));
+        assert!((hit.distance - 1.25 * 5.0f32.sqrt() * 16.0).abs() < 1e-3);
+        // (0, 0), (1, 0), (1, 1), (2, 1) and then the hit
+        assert_eq!(visited, vec![false, false, false, false, true]);
+    }
+
+    #[test]
+    fn negative_diagonal_ray() {
+        // up and to the left, the last step is a row up so it enters through the bottom
+        let hit = map(&[IVec2::new(1, 0)]).raycast(Vec2::new(72.0, -40.0), Vec2::new(-2.0, 1.0), 100.0, solid).unwrap();
+        assert_eq!(hit.cell, IVec2::new(1, 0));
+        assert_eq!(hit.normal, Vec2::new(0.0, -1.0));
+        assert!((hit.distance - 1.5 * 5.0f32.sqrt() * 16.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn ray_starting_outside_the_map() {
+        let tile_map = map(&[IVec2::new(0, 1)]);
+        let hit = tile_map.raycast(Vec2::new(-40.0, -24.0), Vec2::X, 100.0, solid).unwrap();
+        assert_eq!(hit.cell, IVec2::new(0, 1));
+        assert_eq!(hit.normal, Vec2::new(-1.0, 0.0));
+        assert!((hit.distance - 40.0).abs() < 1e-3);
+
+        // moving away, or alongside the map
+        assert!(tile_map.raycast(Vec2::new(-40.0, -24.0), -Vec2::X, 1000.0, solid).is_none());
+        assert!(tile_map.raycast(Vec2::new(8.0, 40.0), Vec2::X, 1000.0, solid).is_none());
+    }
+
+    #[test]
+    fn ray_starting_on_a_border_or_inside_a_hit() {
+        // on the border between cells 1 and 2, going left hits cell 1 right away
+        let hit = map(&[IVec2::new(1, 1)]).raycast(Vec2::new(32.0, -24.0), -Vec2::X, 100.0, solid).unwrap();
+        assert_eq!(hit.cell, IVec2::new(1, 1));
+        assert_eq!(hit.normal, Vec2::new(1.0, 0.0));
+        assert_eq!(hit.distance, 0.0);
+
+        // starting inside a solid cell has no side it came through
+        let hit = map(&[IVec2::new(2, 1)]).raycast(Vec2::new(40.0, -24.0), Vec2::X, 100.0, solid).unwrap();
+        assert_eq!(hit.cell, IVec2::new(2, 1));
+        assert_eq!(hit.normal, Vec2::ZERO);
+        assert_eq!(hit.distance, 0.0);
+    }
+
+    #[test]
+    fn ray_stops_at_max_distance() {
+        let tile_map = map(&[IVec2::new(3, 1)]);
+        assert!(tile_map.raycast(Vec2::new(8.0, -24.0), Vec2::X, 30.0, solid).is_none());
+        assert!(tile_map.raycast(Vec2::new(8.0, -24.0), Vec2::ZERO, 100.0, solid).is_none());
+    }
+}
@@ -0,0 +1,220 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use ldtk_rust::Level;
+use std::collections::{HashMap, HashSet};
+
+use crate::coords::LevelCoords;
+use crate::entities::EntitySpawnRegistry;
+use crate::fields::LevelSettings;
+use crate::ldtk::{LdtkLevel, LdtkProject};
+use crate::level::{self, ActiveLevel, LevelBounds, LevelCollision, LevelLoadError};
+use crate::player::Player;
+use crate::scale::WorldScale;
+
+pub struct LoadedLevel {
+    pub bounds: LevelBounds,
+    pub root: Entity,
+    // Kept so changes to the level's .ldtkl file can be told apart from other levels
+    pub external_level: Option<Handle<LdtkLevel>>,
+}
+
+// Lays levels out at their LDtk world position and streams in neighbouring
+// levels as the player gets close to them. Inserted for a LoadLevel with a
+// world_load_distance, without it only the requested level is spawned,
+// centered on the screen.
+pub struct LevelWorld {
+    // How close (in world pixels) the player has to get to a neighbour before it is loaded.
+    // Levels are unloaded again once the player is twice as far away.
+    pub load_distance: f32,
+    pub current: Option<String>,
+    pub loaded: HashMap<String, LoadedLevel>,
+    // External level files that are still loading
+    pub loading: HashMap<String, Handle<LdtkLevel>>,
+    // Levels that failed to load, these aren't retried until the world is reloaded
+    pub failed: HashSet<String>,
+}
+
+impl LevelWorld {
+    pub fn new(load_distance: f32) -> Self {
+        Self {
+            load_distance,
+            current: None,
+            loaded: HashMap::new(),
+            loading: HashMap::new(),
+            failed: HashSet::new(),
+        }
+    }
+}
+
+pub struct LevelEntered {
+    pub level_id: String,
+}
+
+pub struct LevelExited {
+    pub level_id: String,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn stream_world_levels(
+    mut commands: Commands,
+    world: Option<ResMut<LevelWorld>>,
+    active_level: Option<Res<ActiveLevel>>,
+    asset_server: Res<AssetServer>,
+    projects: Res<Assets<LdtkProject>>,
+    external_levels: Res<Assets<LdtkLevel>>,
+    spawn_registry: Res<EntitySpawnRegistry>,
+    collision: Res<LevelCollision>,
+    world_scale: Res<WorldScale>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    images: Res<Assets<Image>>,
+    mut load_errors: EventWriter<LevelLoadError>,
+    players: Query<(Entity, &ColliderPositionComponent, Option<&Parent>), With<Player>>,
+    mut entered_events: EventWriter<LevelEntered>,
+    mut exited_events: EventWriter<LevelExited>,
+) {
+    let (mut world, active_level) = match (world, active_level) {
+        (Some(world), Some(active_level)) => (world, active_level),
+        _ => return,
+    };
+    let project = match projects.get(&active_level.project) {
+        Some(project) => project,
+        None => return,
+    };
+    let player_position = match players.iter().next() {
+        Some((player, position, parent)) => {
+            // the player moves between levels, so it can't be despawned along with the one it started in
+            if parent.is_some() {
+                commands.entity(player).remove::<Parent>();
+            }
+            Vec2::new(position.translation.x, position.translation.y) * world_scale.physics_scale
+        },
+        None => return,
+    };
+
+    let levels = &project.project.levels;
+    let current = match levels.iter().find(|level| level::level_world_bounds(project, level, &world_scale).contains(player_position)) {
+        Some(current) => current,
+        None => return,
+    };
+
+    if world.current.as_ref() != Some(&current.identifier) {
+        if let Some(previous) = world.current.take() {
+            exited_events.send(LevelExited { level_id: previous });
+        }
+        println!("Entered level: {}", current.identifier);
+        entered_events.send(LevelEntered { level_id: current.identifier.clone() });
+        world.current = Some(current.identifier.clone());
+
+        if let Some(color) = current.bg_color.strip_prefix('#').and_then(|hex| Color::hex(hex).ok()) {
+            commands.insert_resource(ClearColor(color));
+        }
+        commands.insert_resource(LevelSettings::from_fields(&current.identifier, &current.field_instances));
+        let world_position = project.world_position(current);
+        commands.insert_resource(LevelCoords::new(current, project.project.default_grid_size, Some(world_position), *world_scale));
+    }
+
+    // load the current level and any neighbour the player is close to
+    let mut wanted = vec![current];
+    for level in neighbour_levels(project, current) {
+        if level::level_world_bounds(project, level, &world_scale).distance_to(player_position) < world.load_distance {
+            wanted.push(level);
+        }
+    }
+
+    for level in wanted {
+        if world.loaded.contains_key(&level.identifier) || world.failed.contains(&level.identifier) {
+            continue;
+        }
+
+        let mut external_level = world.loading.remove(&level.identifier);
+        let level_data = match level::resolve_level(
+            project,
+            &level.identifier,
+            &asset_server,
+            &external_levels,
+            &mut external_level,
+        ) {
+            Ok(Some(level_data)) => level_data,
+            Ok(None) => {
+                // still loading, try again next frame
+                if let Some(external_level) = external_level {
+                    world.loading.insert(level.identifier.clone(), external_level);
+                }
+                continue;
+            },
+            Err(error) => {
+                println!("Failed to stream in level: {}", error);
+                world.failed.insert(level.identifier.clone());
+                load_errors.send(error);
+                continue;
+            }
+        };
+
+        println!("Streaming in level: {}", level.identifier);
+        // the player already exists, it moves between levels instead of being respawned
+        let root = match level::load_level(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            &images,
+            &spawn_registry,
+            &collision,
+            &world_scale,
+            project,
+            level_data,
+            true,
+            &["Player"],
+        ) {
+            Ok(root) => root,
+            Err(error) => {
+                println!("Failed to stream in level: {}", error);
+                world.failed.insert(level.identifier.clone());
+                load_errors.send(error);
+                continue;
+            }
+        };
+        world.loaded.insert(level.identifier.clone(), LoadedLevel {
+            bounds: level::level_world_bounds(project, level, &world_scale),
+            root,
+            external_level,
+        });
+    }
+
+    // unload levels the player has moved well away from
+    let unload_distance = world.load_distance * 2.0;
+    let far_away: Vec<String> = world.loaded
+        .iter()
+        .filter(|(level_id, loaded)| {
+            **level_id != current.identifier && loaded.bounds.distance_to(player_position) > unload_distance
+        })
+        .map(|(level_id, _)| level_id.clone())
+        .collect();
+
+    for level_id in far_away {
+        println!("Streaming out level: {}", level_id);
+        let loaded = world.loaded.remove(&level_id).unwrap();
+        commands.entity(loaded.root).despawn_recursive();
+    }
+}
+
+// Levels touching this one. LDtk leaves the neighbours of linear layouts empty,
+// there they're the levels before and after it.
+fn neighbour_levels<'a>(project: &'a LdtkProject, level: &Level) -> Vec<&'a Level> {
+    let levels = &project.project.levels;
+    if project.world_layout.is_linear() {
+        let index = match levels.iter().position(|other| other.uid == level.uid) {
+            Some(index) => index,
+            None => return Vec::new(),
+        };
+        return levels[index.saturating_sub(1)..(index + 2).min(levels.len())]
+            .iter()
+            .filter(|other| other.uid != level.uid)
+            .collect();
+    }
+
+    level.neighbours
+        .iter()
+        .filter_map(|neighbour| levels.iter().find(|other| other.uid == neighbour.level_uid))
+        .collect()
+}